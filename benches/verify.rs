@@ -0,0 +1,47 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use pod::{Batch, Domain, Passepartout};
+
+const SIZES: [usize; 3] = [1024, 16384, 65536];
+const STRAGGLER_RATIO: usize = 8; // One payload out of `STRAGGLER_RATIO` is a straggler
+
+fn verify(c: &mut Criterion) {
+    let passepartout = Passepartout::random(SIZES[SIZES.len() - 1] + 1);
+    let (_membership, directory) = passepartout.system(1);
+
+    let mut group = c.benchmark_group("verify");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let batch =
+            Batch::random_with_stragglers(&directory, &passepartout, size, size / STRAGGLER_RATIO);
+
+        group.bench_with_input(BenchmarkId::new("sequential", size), &batch, |b, batch| {
+            b.iter(|| batch.verify(Domain::default(), &directory).unwrap())
+        });
+
+        group.bench_with_input(BenchmarkId::new("parallel", size), &batch, |b, batch| {
+            b.iter(|| batch.par_verify(Domain::default(), &directory).unwrap())
+        });
+
+        // Without stragglers, aggregating reducer public keys dominates
+        let batch = Batch::random(&directory, &passepartout, size);
+
+        group.bench_with_input(
+            BenchmarkId::new("sequential-reduction", size),
+            &batch,
+            |b, batch| b.iter(|| batch.verify(Domain::default(), &directory).unwrap()),
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("parallel-reduction", size),
+            &batch,
+            |b, batch| b.iter(|| batch.par_verify(Domain::default(), &directory).unwrap()),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, verify);
+criterion_main!(benches);
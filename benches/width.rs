@@ -0,0 +1,55 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use pod::{Batch, CompressedBatch, Passepartout};
+
+const SIZE: usize = 65536;
+const WIDTHS: [usize; 5] = [1, 4, 16, 64, 256];
+
+fn width(c: &mut Criterion) {
+    let passepartout = Passepartout::random(SIZE + 1);
+    let (_membership, directory) = passepartout.system(1);
+
+    let mut group = c.benchmark_group("width");
+    group.sample_size(10);
+
+    for width in WIDTHS {
+        let batch = Batch::random_with_width(&directory, &passepartout, SIZE, 0, width);
+
+        let root = batch.root();
+        let payload = batch.payloads().next().unwrap().clone();
+        let proof = batch.prove(payload.id, payload.sequence).unwrap();
+
+        let compressed = bincode::serialize(&batch.compress()).unwrap();
+
+        // Dominated by building the Merkle tree
+        group.throughput(Throughput::Bytes(compressed.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("decompress", width),
+            &compressed,
+            |b, compressed| {
+                b.iter(|| {
+                    bincode::deserialize::<CompressedBatch>(compressed.as_slice())
+                        .unwrap()
+                        .decompress()
+                        .unwrap()
+                })
+            },
+        );
+
+        // Throughput is measured in proof bytes, so that reports show how
+        // proofs grow with `width`
+        let proof_size = bincode::serialize(&proof).unwrap().len();
+        group.throughput(Throughput::Bytes(proof_size as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("verify_proof", width),
+            &proof,
+            |b, proof| b.iter(|| proof.verify(root, &payload).unwrap()),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, width);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use once_cell::sync::Lazy;
+
+use pod::{fuzzing, Membership, Passepartout};
+
+static MEMBERSHIP: Lazy<Membership> = Lazy::new(|| Passepartout::from_seed(0, 4).system(4).0);
+
+fuzz_target!(|data: &[u8]| {
+    fuzzing::verify_certificate(&MEMBERSHIP, data);
+});
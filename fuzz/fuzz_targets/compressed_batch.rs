@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use once_cell::sync::Lazy;
+
+use pod::{fuzzing, Directory, Passepartout};
+
+static DIRECTORY: Lazy<Directory> = Lazy::new(|| Passepartout::from_seed(0, 257).system(1).1);
+
+fuzz_target!(|data: &[u8]| {
+    fuzzing::decode_batch(&DIRECTORY, data);
+});
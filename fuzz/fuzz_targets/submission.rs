@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use once_cell::sync::Lazy;
+
+use pod::{fuzzing, CertificateVerifier, Passepartout};
+
+static VERIFIER: Lazy<CertificateVerifier> =
+    Lazy::new(|| CertificateVerifier::new(Passepartout::from_seed(0, 4).system(4).0));
+
+fuzz_target!(|data: &[u8]| {
+    fuzzing::process_submission(&VERIFIER, data);
+});
@@ -1,5 +1,9 @@
 use crate::{
-    batch::{BroadcastStatement, CompressedBatch, Message, Payload, ReductionStatement},
+    batch::{
+        BatchBuilder, BatchReport, BroadcastStatement, CompressedBatch, Compression,
+        InclusionProof, Payload, ReductionStatement,
+    },
+    crypto::Domain,
     directory::Directory,
     passepartout::Passepartout,
 };
@@ -8,21 +12,49 @@ use doomstack::{here, Doom, ResultExt, Top};
 
 use rand::prelude::*;
 
-use std::{collections::BTreeMap, convert::TryInto, iter};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
 
-use talk::crypto::primitives::{hash::Hash, multi::Signature as MultiSignature, sign::Signature};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    iter,
+    sync::Arc,
+};
 
-use varcram::VarCram;
+use talk::crypto::{
+    primitives::{
+        hash::Hash,
+        multi::{PublicKey as MultiPublicKey, Signature as MultiSignature},
+        sign::Signature,
+    },
+    KeyCard,
+};
 
 use zebra::vector::Vector;
 
-pub(in crate::batch) const NIBBLE: usize = 16; // TODO: Find a more appropriate name
-const NULL_ID: u64 = u64::MAX;
+/// Default number of payloads per Merkle leaf. Wider leaves mean fewer
+/// hashes to build the tree, but larger inclusion proofs.
+pub const DEFAULT_WIDTH: usize = 16;
+
+// Bounds the width announced by a `CompressedBatch`, which is untrusted
+pub(in crate::batch) const MAX_WIDTH: usize = 4096;
+pub(in crate::batch) const NULL_ID: u64 = u64::MAX;
+
+// Reducer public keys aggregated by every `rayon` task of `par_inspect`
+const KEY_CHUNK: usize = 1024;
 
 pub struct Batch {
-    payloads: Vector<[Payload; NIBBLE]>,
+    width: usize,
+    payloads: Vector<Vec<Payload>>,
+    // Covers every client with at least one non-straggler payload, once
     reduction: Option<MultiSignature>,
-    stragglers: BTreeMap<u64, Signature>,
+    stragglers: BTreeMap<(u64, u64), Signature>, // Keyed by `(id, sequence)`
+    // Ids whose payloads are still part of the Merkle tree (so that the
+    // reduction of every other client remains valid), but are neither
+    // verified nor delivered
+    excluded: BTreeSet<u64>,
 }
 
 #[derive(Doom)]
@@ -32,92 +64,269 @@ pub enum BatchError {
     DeserializeFailed { source: bincode::Error },
     #[doom(description("Batch invalid"))]
     BatchInvalid,
+    #[doom(description("Batch empty"))]
+    EmptyBatch,
+    #[doom(description("Unknown id: {}", id))]
+    UnknownId { id: u64 },
+    #[doom(description("Corrupted keycard: {}", id))]
+    KeyCardCorrupted { id: u64 },
+    #[doom(description("Malformed ids"))]
+    MalformedIds,
+    #[doom(description("Length mismatch ({} ids, {} messages)", ids, messages))]
+    LengthMismatch { ids: usize, messages: usize },
+    #[doom(description("Unsorted ids"))]
+    UnsortedIds,
+    #[doom(description("Straggler not in batch: ({}, {})", id, sequence))]
+    StragglerNotInBatch { id: u64, sequence: u64 },
+    #[doom(description("Unknown compression: {}", header))]
+    UnknownCompression { header: u8 },
+    #[doom(description("Malformed messages"))]
+    MalformedMessages,
+    #[doom(description("Duplicate payload: ({}, {})", id, sequence))]
+    DuplicatePayload { id: u64, sequence: u64 },
+    #[doom(description("Reduction missing"))]
+    ReductionMissing,
+    #[doom(description("Reduction unexpected (all payloads are stragglers)"))]
+    ReductionUnexpected,
+    #[doom(description("Reduction invalid"))]
+    ReductionInvalid,
+    #[doom(description("Invalid leaf width: {}", width))]
+    InvalidWidth { width: usize },
+    #[doom(description("Inclusion proof invalid"))]
+    InclusionProofInvalid,
+    #[doom(description("Excluded id not in batch: {}", id))]
+    ExcludedNotInBatch { id: u64 },
 }
 
 impl Batch {
+    /// A batch of `size` random payloads from clients in `directory`, signed
+    /// in the default `Domain`.
     pub fn random(directory: &Directory, passepartout: &Passepartout, size: usize) -> Self {
+        Batch::random_with_stragglers(directory, passepartout, size, 0)
+    }
+
+    /// Like `random`, but `stragglers` randomly chosen payloads are signed
+    /// individually instead of being covered by the reduction.
+    pub fn random_with_stragglers(
+        directory: &Directory,
+        passepartout: &Passepartout,
+        size: usize,
+        stragglers: usize,
+    ) -> Self {
+        Batch::random_with_width(directory, passepartout, size, stragglers, DEFAULT_WIDTH)
+    }
+
+    /// Like `random_with_stragglers`, with `width` payloads per Merkle leaf.
+    pub fn random_with_width(
+        directory: &Directory,
+        passepartout: &Passepartout,
+        size: usize,
+        stragglers: usize,
+        width: usize,
+    ) -> Self {
         let range = 0..(directory.capacity() as u64);
         let ids = range.into_iter().choose_multiple(&mut thread_rng(), size);
 
-        let mut payloads = ids
-            .iter()
-            .copied()
-            .map(|id| {
-                let message: [u8; 8] = random();
-                Payload { id, message }
-            })
+        let keychain = |id| {
+            let keycard = directory.keycard(id).unwrap();
+            passepartout.keychain(keycard.identity())
+        };
+
+        let mut builder = BatchBuilder::with_width(width).unwrap();
+
+        for (index, id) in ids.iter().copied().enumerate() {
+            let message: [u8; 8] = random();
+
+            let payload = Payload {
+                id,
+                sequence: 0,
+                message,
+            };
+
+            if index < stragglers {
+                let signature = keychain(id)
+                    .sign(&BroadcastStatement::new(Domain::default(), 0, message))
+                    .unwrap();
+
+                builder.add_straggler(payload, signature).unwrap();
+            } else {
+                builder.add(payload).unwrap();
+            }
+        }
+
+        let sealed = builder.seal().unwrap();
+        let statement = ReductionStatement::new(Domain::default(), sealed.root());
+
+        let reductions = sealed
+            .reducers()
+            .map(|id| keychain(id).multisign(&statement).unwrap())
             .collect::<Vec<_>>();
 
-        payloads.sort_unstable_by_key(|payload| payload.id);
+        let reduction = if reductions.is_empty() {
+            None
+        } else {
+            Some(MultiSignature::aggregate(reductions).unwrap())
+        };
 
-        let payloads = Batch::vectorize_payloads(payloads);
-        let root = payloads.root();
+        sealed
+            .finalize(Domain::default(), directory, reduction)
+            .unwrap()
+    }
 
-        let reductions = ids.into_iter().map(|id| {
-            let keycard = directory.keycard(id).unwrap();
-            let keychain = passepartout.keychain(keycard.identity());
+    /// Checks the ids of a batch received from the network, before any
+    /// payload is built out of them.
+    pub(in crate::batch) fn check_ids(
+        ids: &[u64],
+        sequences: usize,
+        messages: usize,
+    ) -> Result<(), Top<BatchError>> {
+        if ids.len() != sequences {
+            return BatchError::MalformedIds.fail().spot(here!());
+        }
 
-            keychain.multisign(&ReductionStatement::new(root)).unwrap()
-        });
+        if ids.len() != messages {
+            return BatchError::LengthMismatch {
+                ids: ids.len(),
+                messages,
+            }
+            .fail()
+            .spot(here!());
+        }
 
-        let reduction = Some(MultiSignature::aggregate(reductions).unwrap());
-        let stragglers = BTreeMap::new();
+        if ids.is_empty() {
+            return BatchError::EmptyBatch.fail().spot(here!());
+        }
 
-        Batch {
-            payloads,
-            reduction,
-            stragglers,
+        // `NULL_ID` is reserved for padding, which is never sent
+        if ids.contains(&NULL_ID) {
+            return BatchError::UnknownId { id: NULL_ID }.fail().spot(here!());
         }
+
+        Ok(())
     }
 
-    pub(in crate::batch) fn from_compressed_batch(
-        ids: VarCram,
-        messages: Vec<Message>,
-        reduction: Option<MultiSignature>,
-        stragglers: BTreeMap<u64, Signature>,
-    ) -> Self {
-        let ids = ids.uncram().unwrap(); // TODO: Handle `None` case (`CompressedBatch` might be malformed)
+    // `excluded` is chosen by the broker: every excluded id must have payloads
+    // in the batch, which also bounds its size
+    pub(in crate::batch) fn check_excluded(
+        ids: &[u64],
+        excluded: &BTreeSet<u64>,
+    ) -> Result<(), Top<BatchError>> {
+        let present = ids
+            .iter()
+            .filter(|id| excluded.contains(id))
+            .collect::<BTreeSet<_>>();
 
-        let payloads = ids
-            .into_iter()
-            .zip(messages.into_iter())
-            .map(|(id, message)| Payload { id, message })
-            .collect::<Vec<_>>();
+        if let Some(id) = excluded.iter().find(|id| !present.contains(id)) {
+            return BatchError::ExcludedNotInBatch { id: *id }
+                .fail()
+                .spot(here!());
+        }
+
+        Ok(())
+    }
 
-        let payloads = Batch::vectorize_payloads(payloads);
+    pub(in crate::batch) fn check_width(width: usize) -> Result<(), Top<BatchError>> {
+        if width == 0 || width > MAX_WIDTH {
+            return BatchError::InvalidWidth { width }.fail().spot(here!());
+        }
+
+        Ok(())
+    }
+
+    pub(in crate::batch) fn from_payloads<P>(
+        width: usize,
+        payloads: P,
+        reduction: Option<MultiSignature>,
+        stragglers: BTreeMap<(u64, u64), Signature>,
+        excluded: BTreeSet<u64>,
+    ) -> Self
+    where
+        P: IntoIterator<Item = Payload>,
+    {
+        Batch::from_parts(
+            width,
+            Batch::vectorize_payloads(width, payloads),
+            reduction,
+            stragglers,
+            excluded,
+        )
+    }
 
+    pub(in crate::batch) fn from_parts(
+        width: usize,
+        payloads: Vector<Vec<Payload>>,
+        reduction: Option<MultiSignature>,
+        stragglers: BTreeMap<(u64, u64), Signature>,
+        excluded: BTreeSet<u64>,
+    ) -> Self {
         Batch {
+            width,
             payloads,
             reduction,
             stragglers,
+            excluded,
         }
     }
 
-    fn vectorize_payloads(mut payloads: Vec<Payload>) -> Vector<[Payload; NIBBLE]> {
-        payloads.extend(
-            iter::repeat(Payload {
-                id: NULL_ID,
-                message: [u8::MAX; 8],
-            })
-            .take(NIBBLE - 1),
-        );
-
-        let payloads = payloads
-            .chunks_exact(NIBBLE)
-            .map(|chunk| {
-                let chunk: &[Payload; NIBBLE] = chunk.try_into().unwrap();
-                chunk.clone()
-            })
-            .collect::<Vec<_>>();
+    // Packs `payloads` directly into `width`-wide leaves, padding the last
+    // one, without collecting them first
+    pub(in crate::batch) fn vectorize_payloads<P>(width: usize, payloads: P) -> Vector<Vec<Payload>>
+    where
+        P: IntoIterator<Item = Payload>,
+    {
+        let mut payloads = payloads.into_iter().peekable();
+        let mut leaves = Vec::with_capacity((payloads.size_hint().0 + width - 1) / width);
+
+        while payloads.peek().is_some() {
+            let leaf = (0..width)
+                .map(|_| payloads.next().unwrap_or_else(Payload::padding))
+                .collect::<Vec<_>>();
+
+            leaves.push(leaf);
+        }
 
-        Vector::new(payloads).unwrap()
+        Vector::new(leaves).unwrap()
     }
 
     pub fn root(&self) -> Hash {
         self.payloads.root()
     }
 
+    /// Number of payloads per Merkle leaf.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Proves that the `sequence`-th payload of `id` is part of the batch.
+    /// Returns `None` if no such payload is in the batch.
+    pub fn prove(&self, id: u64, sequence: u64) -> Option<InclusionProof> {
+        let key = (id, sequence);
+        let leaves = self.payloads.items();
+
+        // Payloads are sorted across leaves (padding, being `NULL_ID`, comes last)
+        let index = leaves
+            .partition_point(|leaf| leaf[0].key() <= key)
+            .checked_sub(1)?;
+
+        let leaf = &leaves[index];
+
+        if id == NULL_ID || !leaf.iter().any(|payload| payload.key() == key) {
+            return None;
+        }
+
+        Some(InclusionProof::new(
+            leaf.clone(),
+            self.payloads.prove(index),
+        ))
+    }
+
     pub fn payloads(&self) -> impl Iterator<Item = &Payload> {
+        self.entries()
+            .filter(move |payload| !self.excluded.contains(&payload.id))
+    }
+
+    // Includes the payloads of excluded ids
+    fn entries(&self) -> impl Iterator<Item = &Payload> {
         self.payloads
             .items()
             .iter()
@@ -126,69 +335,364 @@ impl Batch {
     }
 
     pub fn compress(self) -> CompressedBatch {
-        CompressedBatch::from_batch(self.payloads, self.reduction, self.stragglers)
+        self.compress_with(Compression::default())
     }
 
-    pub fn verify(&self, directory: &Directory) -> Result<(), Top<BatchError>> {
-        let mut payloads = self.payloads();
-        let mut last = payloads.next().unwrap().id; // TODO: Think of what you've done
+    pub fn compress_with(self, compression: Compression) -> CompressedBatch {
+        CompressedBatch::from_batch(
+            self.width,
+            self.payloads,
+            self.reduction,
+            self.stragglers,
+            self.excluded,
+            compression,
+        )
+    }
 
-        for next in payloads {
-            if next.id <= last {
-                return BatchError::BatchInvalid.fail();
-            }
+    /// Checks every signature in `self`, which must have been produced in
+    /// `domain`.
+    pub fn verify(&self, domain: Domain, directory: &Directory) -> Result<(), Top<BatchError>> {
+        if self.inspect(domain, directory)?.is_valid() {
+            Ok(())
+        } else {
+            BatchError::BatchInvalid.fail()
+        }
+    }
 
-            last = next.id;
+    /// Like `verify`, but checks stragglers, looks up reducer `KeyCard`s and
+    /// aggregates their public keys across `rayon` threads, leaving a single
+    /// pairing check for the reduction. Meant for large batches: a server
+    /// already verifies several batches concurrently.
+    pub fn par_verify(&self, domain: Domain, directory: &Directory) -> Result<(), Top<BatchError>> {
+        if self.par_inspect(domain, directory)?.is_valid() {
+            Ok(())
+        } else {
+            BatchError::BatchInvalid.fail()
         }
+    }
 
-        let mut stragglers = self.stragglers.iter().peekable();
-        let mut reducers = Vec::with_capacity(self.payloads.len());
+    /// Like `verify`, but instead of failing on the first invalid signature,
+    /// reports which stragglers are invalid and whether the reduction is.
+    /// Structural errors (e.g., unsorted ids) still fail the whole batch.
+    pub fn inspect(
+        &self,
+        domain: Domain,
+        directory: &Directory,
+    ) -> Result<BatchReport, Top<BatchError>> {
+        self.check_structure()?;
+
+        let mut invalid_stragglers = Vec::new();
+        let mut reducers = Vec::with_capacity(self.payloads.len() * self.width);
+        let mut last_reducer = None;
 
         for payload in self.payloads() {
-            if let Some((id, signature)) = stragglers.peek().cloned() {
-                if payload.id == *id {
-                    signature
-                        .verify(
-                            &directory.keycard(*id).unwrap(),
-                            &BroadcastStatement::new(payload.message),
-                        )
-                        .pot(BatchError::BatchInvalid, here!())?;
+            if self.stragglers.contains_key(&payload.key()) {
+                if !self.verify_straggler(domain, directory, payload)? {
+                    invalid_stragglers.push(payload.key());
+                }
+            } else if last_reducer != Some(payload.id) {
+                // Payloads are sorted by id: each reducer is counted once,
+                // however many payloads it has in the batch
+                reducers.push(Batch::keycard(directory, payload.id)?);
+                last_reducer = Some(payload.id);
+            }
+        }
+
+        let reduction_valid = self.verify_reduction(domain, reducers.as_slice());
+
+        Ok(BatchReport {
+            invalid_stragglers,
+            reduction_valid,
+        })
+    }
+
+    /// Parallel version of `inspect` (see `par_verify`).
+    pub fn par_inspect(
+        &self,
+        domain: Domain,
+        directory: &Directory,
+    ) -> Result<BatchReport, Top<BatchError>> {
+        self.check_structure()?;
+
+        let (stragglers, reducers): (Vec<&Payload>, Vec<&Payload>) = self
+            .payloads()
+            .partition(|payload| self.stragglers.contains_key(&payload.key()));
+
+        let invalid_stragglers = stragglers
+            .par_iter()
+            .filter_map(
+                |payload| match self.verify_straggler(domain, directory, payload) {
+                    Ok(true) => None,
+                    Ok(false) => Some(Ok(payload.key())),
+                    Err(error) => Some(Err(error)),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut reducers = reducers
+            .into_iter()
+            .map(|payload| payload.id)
+            .collect::<Vec<_>>();
 
+        reducers.dedup();
+
+        let reducers = reducers
+            .par_iter()
+            .map(|id| Batch::keycard(directory, *id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let reduction_valid = self.par_verify_reduction(domain, reducers.as_slice());
+
+        Ok(BatchReport {
+            invalid_stragglers,
+            reduction_valid,
+        })
+    }
+
+    fn check_structure(&self) -> Result<(), Top<BatchError>> {
+        let mut last = None;
+        let mut stragglers = self.stragglers.keys().copied().peekable();
+
+        for payload in self.entries() {
+            if last.map(|last| payload.key() <= last).unwrap_or(false) {
+                return BatchError::UnsortedIds.fail().spot(here!());
+            }
+
+            last = Some(payload.key());
+
+            // Both `self.entries()` and `stragglers` are sorted: a straggler
+            // smaller than the current key matches no entry in the batch
+            if let Some((id, sequence)) = stragglers.peek().copied() {
+                if (id, sequence) < payload.key() {
+                    return BatchError::StragglerNotInBatch { id, sequence }
+                        .fail()
+                        .spot(here!());
+                } else if (id, sequence) == payload.key() {
                     stragglers.next();
                 }
-            } else {
-                reducers.push(directory.keycard(payload.id).unwrap());
             }
         }
 
-        if reducers.len() > 0 {
-            if let Some(reduction) = self.reduction {
-                reduction
-                    .verify(
-                        reducers.into_iter(),
-                        &ReductionStatement::new(self.payloads.root()),
-                    )
-                    .pot(BatchError::BatchInvalid, here!())?;
-            } else {
-                return BatchError::BatchInvalid.fail();
-            }
+        if last.is_none() {
+            return BatchError::EmptyBatch.fail().spot(here!());
+        }
+
+        if let Some((id, sequence)) = stragglers.next() {
+            return BatchError::StragglerNotInBatch { id, sequence }
+                .fail()
+                .spot(here!());
         }
 
         Ok(())
     }
+
+    pub(in crate::batch) fn keycard(
+        directory: &Directory,
+        id: u64,
+    ) -> Result<Arc<KeyCard>, Top<BatchError>> {
+        match directory.try_keycard(id) {
+            Ok(Some(keycard)) => Ok(keycard),
+            Ok(None) => BatchError::UnknownId { id }.fail().spot(here!()),
+            Err(_) => BatchError::KeyCardCorrupted { id }.fail().spot(here!()),
+        }
+    }
+
+    fn verify_straggler(
+        &self,
+        domain: Domain,
+        directory: &Directory,
+        payload: &Payload,
+    ) -> Result<bool, Top<BatchError>> {
+        let signature = self.stragglers.get(&payload.key()).unwrap();
+        let keycard = Batch::keycard(directory, payload.id)?;
+
+        Ok(signature
+            .verify(
+                keycard.as_ref(),
+                &BroadcastStatement::new(domain, payload.sequence, payload.message),
+            )
+            .is_ok())
+    }
+
+    pub(in crate::batch) fn verify_reduction(
+        &self,
+        domain: Domain,
+        reducers: &[Arc<KeyCard>],
+    ) -> bool {
+        // With no reducers, there is nothing to reduce: a reduction is invalid
+        if reducers.is_empty() {
+            return self.reduction.is_none();
+        }
+
+        if let Some(reduction) = self.reduction {
+            reduction
+                .verify(
+                    reducers.iter().map(Arc::as_ref),
+                    &ReductionStatement::new(domain, self.payloads.root()),
+                )
+                .is_ok()
+        } else {
+            false
+        }
+    }
+
+    // Like `verify_reduction`, but aggregates the public keys of `reducers`
+    // across `rayon` threads, `KEY_CHUNK` at a time
+    fn par_verify_reduction(&self, domain: Domain, reducers: &[Arc<KeyCard>]) -> bool {
+        if reducers.is_empty() {
+            return self.reduction.is_none();
+        }
+
+        let public_key = reducers
+            .par_chunks(KEY_CHUNK)
+            .map(|chunk| {
+                MultiPublicKey::aggregate(chunk.iter().map(|keycard| keycard.multi_public_key()))
+                    .ok()
+            })
+            .reduce_with(|left, right| MultiPublicKey::aggregate([left?, right?]).ok())
+            .flatten();
+
+        match (self.reduction, public_key) {
+            (Some(reduction), Some(public_key)) => reduction
+                .verify_raw(
+                    iter::once(&public_key),
+                    &ReductionStatement::new(domain, self.payloads.root()),
+                )
+                .is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Given the individual reduction shards a broker aggregated, returns the
+    /// ids whose shard is invalid (or unknown to `directory`). Shards are
+    /// verified by bisection: each aggregate that fails verification is split
+    /// in half, so `k` invalid shards out of `n` cost `O(k log n)` checks.
+    pub fn locate_invalid_reductions(
+        domain: Domain,
+        directory: &Directory,
+        root: Hash,
+        shards: &[(u64, MultiSignature)],
+    ) -> Vec<u64> {
+        let statement = ReductionStatement::new(domain, root);
+        let mut culprits = Vec::new();
+
+        Batch::bisect(directory, &statement, shards, &mut culprits);
+
+        culprits.sort_unstable();
+        culprits
+    }
+
+    fn bisect(
+        directory: &Directory,
+        statement: &ReductionStatement,
+        shards: &[(u64, MultiSignature)],
+        culprits: &mut Vec<u64>,
+    ) {
+        if shards.is_empty() {
+            return;
+        }
+
+        let keycards = shards
+            .iter()
+            .map(|(id, _)| directory.try_keycard(*id).ok().flatten())
+            .collect::<Option<Vec<_>>>();
+
+        let valid = keycards
+            .and_then(|keycards| {
+                let signature =
+                    MultiSignature::aggregate(shards.iter().map(|(_, shard)| *shard)).ok()?;
+
+                Some(
+                    signature
+                        .verify(keycards.iter().map(Arc::as_ref), statement)
+                        .is_ok(),
+                )
+            })
+            .unwrap_or(false);
+
+        if valid {
+            return;
+        }
+
+        if shards.len() == 1 {
+            culprits.push(shards[0].0);
+            return;
+        }
+
+        let (left, right) = shards.split_at(shards.len() / 2);
+
+        Batch::bisect(directory, statement, left, culprits);
+        Batch::bisect(directory, statement, right, culprits);
+    }
+
+    /// Excludes `ids` (with all their payloads) from `self` and replaces its
+    /// reduction. The payloads of excluded ids remain in the Merkle tree, so
+    /// `root()` is unchanged and `reduction` can be aggregated from the (still
+    /// valid) shards the broker already collected for the remaining reducers.
+    pub fn exclude<I>(mut self, ids: I, reduction: Option<MultiSignature>) -> Self
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        self.excluded.extend(ids);
+
+        let excluded = &self.excluded;
+
+        self.stragglers.retain(|(id, _), _| !excluded.contains(id));
+
+        self.reduction = reduction;
+        self
+    }
+
+    #[cfg(test)]
+    pub(in crate::batch) fn with_reduction(mut self, reduction: Option<MultiSignature>) -> Self {
+        self.reduction = reduction;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use talk::crypto::primitives::hash;
+
     #[test]
     fn correct_size() {
         let passepartout = Passepartout::random(100);
         let (_membership, directory) = passepartout.system(1);
 
-        let batch = Batch::random(&directory, &passepartout, 42);
-        assert_eq!(batch.payloads.len(), (42 + NIBBLE - 1) / NIBBLE);
+        for width in [1, 7, DEFAULT_WIDTH, 64] {
+            let batch = Batch::random_with_width(&directory, &passepartout, 42, 0, width);
+            assert_eq!(batch.payloads.len(), (42 + width - 1) / width);
+            batch.verify(Domain::default(), &directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn prove() {
+        let passepartout = Passepartout::random(100);
+        let (_membership, directory) = passepartout.system(1);
+
+        for width in [1, 5, DEFAULT_WIDTH] {
+            let batch = Batch::random_with_width(&directory, &passepartout, 42, 0, width);
+            let root = batch.root();
+
+            for payload in batch.payloads() {
+                let proof = batch.prove(payload.id, payload.sequence).unwrap();
+                proof.verify(root, payload).unwrap();
+
+                let mut forged = payload.clone();
+                forged.message[0] ^= 1;
+                assert!(proof.verify(root, &forged).is_err());
+            }
+
+            let missing = (0..100).find(|id| batch.prove(*id, 0).is_none());
+            assert!(missing.is_some());
+            assert!(batch.prove(NULL_ID, u64::MAX).is_none());
+
+            let present = batch.payloads().next().unwrap().id;
+            assert!(batch.prove(present, 1).is_none());
+        }
     }
 
     #[test]
@@ -197,6 +701,166 @@ mod tests {
         let (_membership, directory) = passepartout.system(1);
 
         let batch = Batch::random(&directory, &passepartout, 42);
-        batch.verify(&directory).unwrap();
+        batch.verify(Domain::default(), &directory).unwrap();
+        batch.par_verify(Domain::default(), &directory).unwrap();
+
+        let batch = Batch::random_with_stragglers(&directory, &passepartout, 42, 10);
+        batch.verify(Domain::default(), &directory).unwrap();
+        batch.par_verify(Domain::default(), &directory).unwrap();
+
+        // Signatures do not carry over to other deployments or epochs
+        for domain in [Domain::new(1, 0), Domain::new(0, 1)] {
+            let report = batch.inspect(domain, &directory).unwrap();
+
+            assert!(!report.reduction_valid);
+            assert_eq!(report.invalid_stragglers.len(), 10);
+        }
+
+        // A batch of stragglers has no reducers, and cannot carry a reduction
+        let batch = Batch::random_with_stragglers(&directory, &passepartout, 42, 42);
+        batch.verify(Domain::default(), &directory).unwrap();
+
+        let keychain = passepartout.keychain(directory.keycard(0).unwrap().identity());
+        let reduction = keychain
+            .multisign(&ReductionStatement::new(Domain::default(), batch.root()))
+            .unwrap();
+
+        let batch = batch.with_reduction(Some(reduction));
+
+        assert!(
+            !batch
+                .inspect(Domain::default(), &directory)
+                .unwrap()
+                .reduction_valid
+        );
+        assert!(
+            !batch
+                .par_inspect(Domain::default(), &directory)
+                .unwrap()
+                .reduction_valid
+        );
+    }
+
+    #[test]
+    fn multiple_messages() {
+        let passepartout = Passepartout::random(100);
+        let (_membership, directory) = passepartout.system(1);
+
+        let keychain = |id| passepartout.keychain(directory.keycard(id).unwrap().identity());
+
+        // Every client submits three messages, the second of which as a straggler
+        let mut builder = BatchBuilder::new();
+
+        for id in 0..20 {
+            for sequence in 0..3 {
+                let payload = Payload {
+                    id,
+                    sequence,
+                    message: random(),
+                };
+
+                if sequence == 1 {
+                    let signature = keychain(id)
+                        .sign(&BroadcastStatement::new(
+                            Domain::default(),
+                            sequence,
+                            payload.message,
+                        ))
+                        .unwrap();
+
+                    builder.add_straggler(payload, signature).unwrap();
+                } else {
+                    builder.add(payload).unwrap();
+                }
+            }
+        }
+
+        let sealed = builder.seal().unwrap();
+        assert!(sealed.reducers().eq(0..20u64));
+
+        let statement = ReductionStatement::new(Domain::default(), sealed.root());
+
+        let reduction = MultiSignature::aggregate(
+            sealed
+                .reducers()
+                .map(|id| keychain(id).multisign(&statement).unwrap()),
+        )
+        .unwrap();
+
+        let batch = sealed
+            .finalize(Domain::default(), &directory, Some(reduction))
+            .unwrap();
+
+        assert_eq!(batch.payloads().count(), 60);
+        batch.verify(Domain::default(), &directory).unwrap();
+        batch.par_verify(Domain::default(), &directory).unwrap();
+
+        let batch = batch.exclude([3], Some(reduction));
+        let report = batch.inspect(Domain::default(), &directory).unwrap();
+
+        assert_eq!(batch.payloads().count(), 57);
+        assert!(report.invalid_stragglers.is_empty());
+        assert!(!report.reduction_valid);
+    }
+
+    #[test]
+    fn exclude_invalid_reducers() {
+        let passepartout = Passepartout::random(100);
+        let (_membership, directory) = passepartout.system(1);
+
+        let batch = Batch::random(&directory, &passepartout, 42);
+        let root = batch.root();
+
+        let mut shards = batch
+            .payloads()
+            .map(|payload| {
+                let keycard = directory.keycard(payload.id).unwrap();
+                let keychain = passepartout.keychain(keycard.identity());
+                let shard = keychain
+                    .multisign(&ReductionStatement::new(Domain::default(), root))
+                    .unwrap();
+
+                (payload.id, shard)
+            })
+            .collect::<Vec<_>>();
+
+        let culprit = shards[7].0;
+        let keychain = passepartout.keychain(directory.keycard(culprit).unwrap().identity());
+
+        shards[7].1 = keychain
+            .multisign(&ReductionStatement::new(
+                Domain::default(),
+                hash::hash(&0u64).unwrap(),
+            ))
+            .unwrap();
+
+        let reduction = MultiSignature::aggregate(shards.iter().map(|(_, shard)| *shard)).unwrap();
+        let batch = batch.with_reduction(Some(reduction));
+
+        let report = batch.inspect(Domain::default(), &directory).unwrap();
+        assert!(report.invalid_stragglers.is_empty());
+        assert!(!report.reduction_valid);
+
+        let culprits = Batch::locate_invalid_reductions(
+            Domain::default(),
+            &directory,
+            root,
+            shards.as_slice(),
+        );
+        assert_eq!(culprits, vec![culprit]);
+
+        let reduction = MultiSignature::aggregate(
+            shards
+                .iter()
+                .filter(|(id, _)| *id != culprit)
+                .map(|(_, shard)| *shard),
+        )
+        .unwrap();
+
+        let batch = batch.exclude(culprits, Some(reduction));
+
+        batch.verify(Domain::default(), &directory).unwrap();
+        assert_eq!(batch.root(), root);
+        assert_eq!(batch.payloads().count(), 41);
     }
 }
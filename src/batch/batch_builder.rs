@@ -0,0 +1,308 @@
+use crate::{
+    batch::{
+        batch::{DEFAULT_WIDTH, NULL_ID},
+        Batch, BatchError, Message, Payload,
+    },
+    crypto::Domain,
+    directory::Directory,
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+
+use talk::crypto::primitives::{hash::Hash, multi::Signature as MultiSignature, sign::Signature};
+
+use zebra::vector::Vector;
+
+/// Collects payloads (in any order) into a `Batch`.
+///
+/// Building happens in two steps: `seal` fixes the set of payloads and
+/// computes the root, which reducers must then sign; `SealedBatch::finalize`
+/// attaches the aggregated reduction. Ids are sorted and unique by
+/// construction, and `finalize` checks that the reduction covers every
+/// non-straggler.
+pub struct BatchBuilder {
+    width: usize,
+    payloads: BTreeMap<(u64, u64), Message>,
+    stragglers: BTreeMap<(u64, u64), Signature>,
+}
+
+/// A `Batch` whose payloads are fixed, waiting for its reduction.
+pub struct SealedBatch {
+    width: usize,
+    payloads: Vector<Vec<Payload>>,
+    stragglers: BTreeMap<(u64, u64), Signature>,
+    reducers: Vec<u64>,
+}
+
+impl BatchBuilder {
+    pub fn new() -> Self {
+        BatchBuilder::with_width(DEFAULT_WIDTH).unwrap()
+    }
+
+    /// Builds a batch with `width` payloads per Merkle leaf (see `DEFAULT_WIDTH`).
+    pub fn with_width(width: usize) -> Result<Self, Top<BatchError>> {
+        Batch::check_width(width)?;
+
+        Ok(BatchBuilder {
+            width,
+            payloads: BTreeMap::new(),
+            stragglers: BTreeMap::new(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+
+    /// Adds a payload to be covered by the reduction.
+    pub fn add(&mut self, payload: Payload) -> Result<(), Top<BatchError>> {
+        // `NULL_ID` is reserved for padding
+        if payload.id == NULL_ID {
+            return BatchError::UnknownId { id: NULL_ID }.fail().spot(here!());
+        }
+
+        match self.payloads.entry(payload.key()) {
+            Entry::Vacant(entry) => {
+                entry.insert(payload.message);
+                Ok(())
+            }
+            Entry::Occupied(_) => BatchError::DuplicatePayload {
+                id: payload.id,
+                sequence: payload.sequence,
+            }
+            .fail()
+            .spot(here!()),
+        }
+    }
+
+    /// Adds a payload individually signed by its client (see `BroadcastStatement`).
+    pub fn add_straggler(
+        &mut self,
+        payload: Payload,
+        signature: Signature,
+    ) -> Result<(), Top<BatchError>> {
+        let key = payload.key();
+
+        self.add(payload)?;
+        self.stragglers.insert(key, signature);
+
+        Ok(())
+    }
+
+    pub fn seal(self) -> Result<SealedBatch, Top<BatchError>> {
+        if self.payloads.is_empty() {
+            return BatchError::EmptyBatch.fail().spot(here!());
+        }
+
+        // A client with at least one non-straggler payload signs the
+        // reduction (once)
+        let mut reducers = self
+            .payloads
+            .keys()
+            .filter(|key| !self.stragglers.contains_key(key))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        reducers.dedup();
+
+        // `BTreeMap` iterates in increasing `(id, sequence)` order
+        let payloads = Batch::vectorize_payloads(
+            self.width,
+            self.payloads
+                .into_iter()
+                .map(|((id, sequence), message)| Payload {
+                    id,
+                    sequence,
+                    message,
+                }),
+        );
+
+        Ok(SealedBatch {
+            width: self.width,
+            payloads,
+            stragglers: self.stragglers,
+            reducers,
+        })
+    }
+}
+
+impl Default for BatchBuilder {
+    fn default() -> Self {
+        BatchBuilder::new()
+    }
+}
+
+impl SealedBatch {
+    /// The root every reducer must sign (see `ReductionStatement`).
+    pub fn root(&self) -> Hash {
+        self.payloads.root()
+    }
+
+    /// The ids whose reduction shards must be aggregated into the reduction.
+    pub fn reducers(&self) -> impl Iterator<Item = u64> + '_ {
+        self.reducers.iter().copied()
+    }
+
+    /// Attaches `reduction`, which must be `Some` unless all payloads are
+    /// stragglers, after checking it against the `KeyCard`s of all reducers in
+    /// `directory`. Stragglers are only checked by `Batch::verify`.
+    pub fn finalize(
+        self,
+        domain: Domain,
+        directory: &Directory,
+        reduction: Option<MultiSignature>,
+    ) -> Result<Batch, Top<BatchError>> {
+        match (self.reducers.is_empty(), reduction.is_some()) {
+            (false, false) => return BatchError::ReductionMissing.fail().spot(here!()),
+            (true, true) => return BatchError::ReductionUnexpected.fail().spot(here!()),
+            _ => {}
+        }
+
+        let reducers = self
+            .reducers
+            .iter()
+            .map(|id| Batch::keycard(directory, *id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let batch = Batch::from_parts(
+            self.width,
+            self.payloads,
+            reduction,
+            self.stragglers,
+            BTreeSet::new(),
+        );
+
+        if !batch.verify_reduction(domain, reducers.as_slice()) {
+            return BatchError::ReductionInvalid.fail().spot(here!());
+        }
+
+        Ok(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        batch::{BroadcastStatement, ReductionStatement},
+        crypto::Domain,
+        passepartout::Passepartout,
+    };
+
+    #[test]
+    fn build() {
+        let passepartout = Passepartout::random(100);
+        let (_membership, directory) = passepartout.system(1);
+
+        let keychain = |id| passepartout.keychain(directory.keycard(id).unwrap().identity());
+
+        let mut builder = BatchBuilder::new();
+
+        for id in (0..40).rev() {
+            let payload = Payload {
+                id,
+                sequence: 0,
+                message: [id as u8; 8],
+            };
+
+            if id % 4 == 0 {
+                let signature = keychain(id)
+                    .sign(&BroadcastStatement::new(
+                        Domain::default(),
+                        0,
+                        payload.message,
+                    ))
+                    .unwrap();
+
+                builder.add_straggler(payload, signature).unwrap();
+            } else {
+                builder.add(payload).unwrap();
+            }
+        }
+
+        let error = builder
+            .add(Payload {
+                id: 3,
+                sequence: 0,
+                message: [0; 8],
+            })
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.top(),
+            BatchError::DuplicatePayload { id: 3, sequence: 0 }
+        ));
+
+        let sealed = builder.seal().unwrap();
+        assert_eq!(sealed.reducers().count(), 30);
+
+        let statement = ReductionStatement::new(Domain::default(), sealed.root());
+
+        let reduction = MultiSignature::aggregate(
+            sealed
+                .reducers()
+                .map(|id| keychain(id).multisign(&statement).unwrap()),
+        )
+        .unwrap();
+
+        let root = sealed.root();
+
+        let batch = sealed
+            .finalize(Domain::default(), &directory, Some(reduction))
+            .unwrap();
+
+        assert_eq!(batch.root(), root);
+        assert!(batch.payloads().map(|payload| payload.id).eq(0..40u64));
+        batch.verify(Domain::default(), &directory).unwrap();
+
+        let mut builder = BatchBuilder::new();
+        builder
+            .add(Payload {
+                id: 0,
+                sequence: 0,
+                message: [0; 8],
+            })
+            .unwrap();
+
+        let error = builder
+            .seal()
+            .unwrap()
+            .finalize(Domain::default(), &directory, None)
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), BatchError::ReductionMissing));
+
+        let mut builder = BatchBuilder::new();
+
+        for id in 0..2 {
+            builder
+                .add(Payload {
+                    id,
+                    sequence: 0,
+                    message: [0; 8],
+                })
+                .unwrap();
+        }
+
+        // A reduction missing the shard of reducer 1
+        let sealed = builder.seal().unwrap();
+        let statement = ReductionStatement::new(Domain::default(), sealed.root());
+        let partial = keychain(0).multisign(&statement).unwrap();
+
+        let error = sealed
+            .finalize(Domain::default(), &directory, Some(partial))
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), BatchError::ReductionInvalid));
+
+        let error = BatchBuilder::new().seal().err().unwrap();
+        assert!(matches!(error.top(), BatchError::EmptyBatch));
+    }
+}
@@ -0,0 +1,14 @@
+/// The outcome of `Batch::inspect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchReport {
+    /// `(id, sequence)` pairs (sorted) whose straggler signature is invalid
+    pub invalid_stragglers: Vec<(u64, u64)>,
+    /// `false` if the reduction does not match the (non-excluded) reducers
+    pub reduction_valid: bool,
+}
+
+impl BatchReport {
+    pub fn is_valid(&self) -> bool {
+        self.invalid_stragglers.is_empty() && self.reduction_valid
+    }
+}
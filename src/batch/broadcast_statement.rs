@@ -1,15 +1,28 @@
-use crate::{batch::Message, crypto::Header};
+use crate::{
+    batch::Message,
+    crypto::{Domain, Header},
+};
 
 use serde::Serialize;
 
 use talk::crypto::Statement;
 
+/// Signed by a client to broadcast `message` as its `sequence`-th message,
+/// individually (see `BatchBuilder::add_straggler`).
 #[derive(Debug, Clone, Serialize)]
-pub(crate) struct BroadcastStatement(Message);
+pub struct BroadcastStatement {
+    domain: Domain,
+    sequence: u64,
+    message: Message,
+}
 
 impl BroadcastStatement {
-    pub fn new(message: Message) -> Self {
-        BroadcastStatement(message)
+    pub fn new(domain: Domain, sequence: u64, message: Message) -> Self {
+        BroadcastStatement {
+            domain,
+            sequence,
+            message,
+        }
     }
 }
 
@@ -1,8 +1,10 @@
-use crate::batch::{batch::NIBBLE, Batch, Message, Payload};
+use crate::batch::{batch::NULL_ID, Batch, BatchError, Compression, Payload};
+
+use doomstack::{here, Doom, ResultExt, Top};
 
 use serde::{Deserialize, Serialize};
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use talk::crypto::primitives::{multi::Signature as MultiSignature, sign::Signature};
 
@@ -12,37 +14,230 @@ use zebra::vector::Vector;
 
 #[derive(Serialize, Deserialize)]
 pub struct CompressedBatch {
+    width: u32,
     ids: VarCram,
-    messages: Vec<Message>,
+    sequences: VarCram,
+    compression: u8,
+    messages: Vec<u8>,
     reduction: Option<MultiSignature>,
-    stragglers: BTreeMap<u64, Signature>,
+    stragglers: BTreeMap<(u64, u64), Signature>,
+    excluded: BTreeSet<u64>,
 }
 
 impl CompressedBatch {
     pub(in crate::batch) fn from_batch(
-        payloads: Vector<[Payload; NIBBLE]>,
+        width: usize,
+        payloads: Vector<Vec<Payload>>,
         reduction: Option<MultiSignature>,
-        stragglers: BTreeMap<u64, Signature>,
+        stragglers: BTreeMap<(u64, u64), Signature>,
+        excluded: BTreeSet<u64>,
+        compression: Compression,
     ) -> Self {
-        let mut ids = Vec::with_capacity(payloads.len());
-        let mut messages = Vec::with_capacity(payloads.len());
+        let mut ids = Vec::with_capacity(payloads.len() * width);
+        let mut sequences = Vec::with_capacity(payloads.len() * width);
+        let mut messages = Vec::with_capacity(payloads.len() * width);
 
-        for payload in payloads.items().iter().flatten() {
+        // Padding (see `Batch::vectorize_payloads`) is not sent: the receiver
+        // adds it back before rebuilding the Merkle tree
+        for payload in payloads
+            .items()
+            .iter()
+            .flatten()
+            .filter(|payload| payload.id != NULL_ID)
+        {
             ids.push(payload.id);
-            messages.push(payload.message.clone());
+            sequences.push(payload.sequence);
+            messages.push(payload.message);
         }
 
         let ids = VarCram::cram(ids.as_slice());
+        let sequences = VarCram::cram(sequences.as_slice());
+        let messages = compression.encode(messages.as_slice());
 
         CompressedBatch {
+            width: width as u32,
             ids,
+            sequences,
+            compression: compression.header(),
             messages,
             reduction,
             stragglers,
+            excluded,
+        }
+    }
+
+    pub fn decompress(self) -> Result<Batch, Top<BatchError>> {
+        let width = self.width as usize;
+        Batch::check_width(width)?;
+
+        let (ids, sequences) = match (self.ids.uncram(), self.sequences.uncram()) {
+            (Some(ids), Some(sequences)) => (ids, sequences),
+            _ => return BatchError::MalformedIds.fail().spot(here!()),
+        };
+
+        let messages = Compression::decode(self.compression, self.messages.as_slice(), ids.len())?;
+        Batch::check_ids(ids.as_slice(), sequences.len(), messages.len())?;
+        Batch::check_excluded(ids.as_slice(), &self.excluded)?;
+
+        let payloads =
+            ids.into_iter()
+                .zip(sequences)
+                .zip(messages)
+                .map(|((id, sequence), message)| Payload {
+                    id,
+                    sequence,
+                    message,
+                });
+
+        Ok(Batch::from_payloads(
+            width,
+            payloads,
+            self.reduction,
+            self.stragglers,
+            self.excluded,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        batch::{
+            batch::{DEFAULT_WIDTH, MAX_WIDTH},
+            BroadcastStatement,
+        },
+        crypto::Domain,
+        passepartout::Passepartout,
+    };
+
+    use rand::prelude::*;
+
+    fn compressed(ids: &[u64], messages: usize) -> CompressedBatch {
+        CompressedBatch {
+            width: DEFAULT_WIDTH as u32,
+            ids: VarCram::cram(ids),
+            sequences: VarCram::cram(vec![0; ids.len()].as_slice()),
+            compression: Compression::Plain.header(),
+            messages: Compression::Plain.encode(vec![[0; 8]; messages].as_slice()),
+            reduction: None,
+            stragglers: BTreeMap::new(),
+            excluded: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let passepartout = Passepartout::random(10);
+        let (_membership, directory) = passepartout.system(1);
+
+        let error = compressed(&[1, 2, 3], 2).decompress().err().unwrap();
+        assert!(matches!(error.top(), BatchError::LengthMismatch { .. }));
+
+        let error = compressed(&[], 0).decompress().err().unwrap();
+        assert!(matches!(error.top(), BatchError::EmptyBatch));
+
+        let error = compressed(&[1, u64::MAX], 2).decompress().err().unwrap();
+        assert!(matches!(error.top(), BatchError::UnknownId { .. }));
+
+        let mut batch = compressed(&[1, 2], 2);
+        batch.excluded = [2, 3].into_iter().collect();
+
+        let error = batch.decompress().err().unwrap();
+        assert!(matches!(
+            error.top(),
+            BatchError::ExcludedNotInBatch { id: 3 }
+        ));
+
+        let batch = compressed(&[3, 1], 2).decompress().unwrap();
+        let error = batch.verify(Domain::default(), &directory).err().unwrap();
+        assert!(matches!(error.top(), BatchError::UnsortedIds));
+
+        // Same `(id, sequence)` twice
+        let batch = compressed(&[1, 1], 2).decompress().unwrap();
+        let error = batch.verify(Domain::default(), &directory).err().unwrap();
+        assert!(matches!(error.top(), BatchError::UnsortedIds));
+
+        let batch = compressed(&[1, 1000], 2).decompress().unwrap();
+        let error = batch.verify(Domain::default(), &directory).err().unwrap();
+        assert!(matches!(error.top(), BatchError::UnknownId { id: 1000 }));
+
+        for width in [0, MAX_WIDTH as u32 + 1] {
+            let mut batch = compressed(&[1, 2], 2);
+            batch.width = width;
+
+            let error = batch.decompress().err().unwrap();
+            assert!(matches!(error.top(), BatchError::InvalidWidth { .. }));
         }
+
+        let mut batch = compressed(&[1, 2], 2);
+        batch.compression = u8::MAX;
+
+        let error = batch.decompress().err().unwrap();
+        assert!(matches!(
+            error.top(),
+            BatchError::UnknownCompression { header: u8::MAX }
+        ));
+
+        let mut batch = compressed(&[1, 2], 2);
+        batch.messages.pop();
+
+        let error = batch.decompress().err().unwrap();
+        assert!(matches!(error.top(), BatchError::MalformedMessages));
+
+        let mut batch = compressed(&[1, 2], 2);
+        batch.compression = Compression::Lz4.header();
+
+        let error = batch.decompress().err().unwrap();
+        assert!(matches!(error.top(), BatchError::MalformedMessages));
+
+        let keychain = passepartout.keychain(directory.keycard(3).unwrap().identity());
+        let signature = keychain
+            .sign(&BroadcastStatement::new(Domain::default(), 0, [0; 8]))
+            .unwrap();
+
+        let mut batch = compressed(&[1, 2], 2);
+        batch.stragglers.insert((3, 0), signature);
+
+        let batch = batch.decompress().unwrap();
+        let error = batch.verify(Domain::default(), &directory).err().unwrap();
+        assert!(matches!(
+            error.top(),
+            BatchError::StragglerNotInBatch { id: 3, sequence: 0 }
+        ));
     }
 
-    pub fn decompress(self) -> Batch {
-        Batch::from_compressed_batch(self.ids, self.messages, self.reduction, self.stragglers)
+    #[test]
+    fn round_trip() {
+        let passepartout = Passepartout::random(300);
+        let (_membership, directory) = passepartout.system(1);
+
+        let mut rng = thread_rng();
+
+        for compression in [
+            Compression::Plain,
+            Compression::Dictionary,
+            Compression::Lz4,
+        ] {
+            for _ in 0..10 {
+                let size = rng.gen_range(1..256);
+                let stragglers = rng.gen_range(0..=size);
+
+                let batch =
+                    Batch::random_with_stragglers(&directory, &passepartout, size, stragglers);
+
+                let root = batch.root();
+                let payloads = batch.payloads().cloned().collect::<Vec<_>>();
+
+                let bytes = bincode::serialize(&batch.compress_with(compression)).unwrap();
+                let compressed = bincode::deserialize::<CompressedBatch>(bytes.as_slice()).unwrap();
+                let batch = compressed.decompress().unwrap();
+
+                assert_eq!(batch.root(), root);
+                assert!(batch.payloads().eq(payloads.iter()));
+                batch.verify(Domain::default(), &directory).unwrap();
+            }
+        }
     }
 }
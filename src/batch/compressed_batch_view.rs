@@ -0,0 +1,223 @@
+use crate::batch::{
+    compression::{self, Compression},
+    Batch, BatchError, Message, Payload,
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use talk::crypto::primitives::{hash::Hash, multi::Signature as MultiSignature, sign::Signature};
+
+use varcram::VarCram;
+
+use zebra::vector::Vector;
+
+/// A view over the wire bytes of a `CompressedBatch`. With
+/// `Compression::Plain`, messages are read in place from the wire bytes,
+/// sparing the message vector `CompressedBatch::decompress` allocates.
+/// Messages under any other compression are decoded when the view is built,
+/// and so are ids and sequences (`VarCram` cannot be read in place). `root`
+/// hashes Merkle leaves straight from these columns, without building
+/// payloads.
+pub struct CompressedBatchView<'a> {
+    width: usize,
+    ids: Vec<u64>,
+    sequences: Vec<u64>,
+    messages: Messages<'a>,
+    reduction: Option<MultiSignature>,
+    stragglers: BTreeMap<(u64, u64), Signature>,
+    excluded: BTreeSet<u64>,
+}
+
+// Same layout as `CompressedBatch`, with `messages` borrowed
+#[derive(Deserialize)]
+struct Wire<'a> {
+    width: u32,
+    ids: VarCram,
+    sequences: VarCram,
+    compression: u8,
+    #[serde(borrow)]
+    messages: &'a [u8],
+    reduction: Option<MultiSignature>,
+    stragglers: BTreeMap<(u64, u64), Signature>,
+    excluded: BTreeSet<u64>,
+}
+
+enum Messages<'a> {
+    Columns(&'a [u8]),
+    Decoded(Vec<Message>),
+}
+
+// Serializes (hence hashes) exactly like the `width` payloads of a Merkle leaf
+// (see `Batch::vectorize_payloads`), reading them from the view's columns
+struct Leaf<'v, 'a> {
+    view: &'v CompressedBatchView<'a>,
+    start: usize,
+}
+
+impl<'a> CompressedBatchView<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Top<BatchError>> {
+        let wire = bincode::deserialize::<Wire>(bytes)
+            .map_err(BatchError::deserialize_failed)
+            .map_err(BatchError::into_top)
+            .spot(here!())?;
+
+        let width = wire.width as usize;
+        Batch::check_width(width)?;
+
+        let (ids, sequences) = match (wire.ids.uncram(), wire.sequences.uncram()) {
+            (Some(ids), Some(sequences)) => (ids, sequences),
+            _ => return BatchError::MalformedIds.fail().spot(here!()),
+        };
+
+        let (messages, count) = match Compression::from_header(wire.compression)? {
+            Compression::Plain => (
+                Messages::Columns(wire.messages),
+                compression::column_count(wire.messages)?,
+            ),
+            _ => {
+                let messages = Compression::decode(wire.compression, wire.messages, ids.len())?;
+                let count = messages.len();
+
+                (Messages::Decoded(messages), count)
+            }
+        };
+
+        Batch::check_ids(ids.as_slice(), sequences.len(), count)?;
+        Batch::check_excluded(ids.as_slice(), &wire.excluded)?;
+
+        Ok(CompressedBatchView {
+            width,
+            ids,
+            sequences,
+            messages,
+            reduction: wire.reduction,
+            stragglers: wire.stragglers,
+            excluded: wire.excluded,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn payloads(&self) -> impl Iterator<Item = Payload> + '_ {
+        (0..self.ids.len()).map(move |row| self.payload(row))
+    }
+
+    /// Equivalent to `self.into_batch().root()`, without building the batch.
+    pub fn root(&self) -> Hash {
+        let leaves = (0..self.ids.len())
+            .step_by(self.width)
+            .map(|start| Leaf { view: self, start })
+            .collect::<Vec<_>>();
+
+        Vector::new(leaves).unwrap().root()
+    }
+
+    fn payload(&self, row: usize) -> Payload {
+        Payload {
+            id: self.ids[row],
+            sequence: self.sequences[row],
+            message: self.messages.get(self.ids.len(), row),
+        }
+    }
+
+    pub fn into_batch(self) -> Batch {
+        let CompressedBatchView {
+            width,
+            ids,
+            sequences,
+            messages,
+            reduction,
+            stragglers,
+            excluded,
+        } = self;
+
+        let count = ids.len();
+
+        let payloads = ids
+            .into_iter()
+            .zip(sequences)
+            .enumerate()
+            .map(|(row, (id, sequence))| Payload {
+                id,
+                sequence,
+                message: messages.get(count, row),
+            });
+
+        Batch::from_payloads(width, payloads, reduction, stragglers, excluded)
+    }
+}
+
+impl Serialize for Leaf<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let width = self.view.width;
+        let mut leaf = serializer.serialize_seq(Some(width))?;
+
+        for row in self.start..(self.start + width) {
+            if row < self.view.ids.len() {
+                leaf.serialize_element(&self.view.payload(row))?;
+            } else {
+                leaf.serialize_element(&Payload::padding())?;
+            }
+        }
+
+        leaf.end()
+    }
+}
+
+impl<'a> Messages<'a> {
+    fn get(&self, count: usize, row: usize) -> Message {
+        match self {
+            Messages::Columns(columns) => compression::message_at(columns, count, row),
+            Messages::Decoded(messages) => messages[row],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{crypto::Domain, passepartout::Passepartout};
+
+    #[test]
+    fn matches_decompress() {
+        let passepartout = Passepartout::random(200);
+        let (_membership, directory) = passepartout.system(1);
+
+        for compression in [
+            Compression::Plain,
+            Compression::Dictionary,
+            Compression::Lz4,
+        ] {
+            // A width of 7 pads the last leaf
+            let batch = Batch::random_with_width(&directory, &passepartout, 100, 10, 7);
+            let root = batch.root();
+
+            let bytes = bincode::serialize(&batch.compress_with(compression)).unwrap();
+            let view = CompressedBatchView::new(bytes.as_slice()).unwrap();
+
+            assert_eq!(view.len(), 100);
+
+            let decompressed = bincode::deserialize::<crate::batch::CompressedBatch>(&bytes)
+                .unwrap()
+                .decompress()
+                .unwrap();
+
+            assert!(view.payloads().eq(decompressed.payloads().cloned()));
+            assert_eq!(view.root(), root);
+
+            let batch = view.into_batch();
+            assert_eq!(batch.root(), root);
+            batch.verify(Domain::default(), &directory).unwrap();
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::batch::{BatchError, Message};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use std::{collections::HashMap, convert::TryInto, mem};
+
+use varcram::VarCram;
+
+const MESSAGE_SIZE: usize = mem::size_of::<Message>();
+
+/// How `CompressedBatch` encodes messages. The variant is carried on the wire
+/// as a header byte, so that servers can decode batches compressed with any
+/// of them.
+///
+/// All variants first lay messages out column by column (the first byte of
+/// every message, then the second byte of every message, and so on), which
+/// groups similar bytes together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Compression {
+    /// Columns, uncompressed
+    Plain = 0,
+    /// A table of distinct messages, plus the index of every message in the
+    /// table. Best when many clients submit the same messages.
+    Dictionary = 1,
+    /// Columns, LZ4-compressed
+    Lz4 = 2,
+}
+
+impl Compression {
+    pub(in crate::batch) fn header(&self) -> u8 {
+        *self as u8
+    }
+
+    pub(in crate::batch) fn from_header(header: u8) -> Result<Self, Top<BatchError>> {
+        match header {
+            0 => Ok(Compression::Plain),
+            1 => Ok(Compression::Dictionary),
+            2 => Ok(Compression::Lz4),
+            header => BatchError::UnknownCompression { header }
+                .fail()
+                .spot(here!()),
+        }
+    }
+
+    pub(in crate::batch) fn encode(&self, messages: &[Message]) -> Vec<u8> {
+        match self {
+            Compression::Plain => columns(messages),
+            Compression::Dictionary => {
+                let mut table = messages.to_vec();
+                table.sort_unstable();
+                table.dedup();
+
+                let positions = table
+                    .iter()
+                    .enumerate()
+                    .map(|(position, message)| (*message, position as u64))
+                    .collect::<HashMap<_, _>>();
+
+                let indices = messages
+                    .iter()
+                    .map(|message| *positions.get(message).unwrap())
+                    .collect::<Vec<_>>();
+
+                let table = columns(table.as_slice());
+                let indices = VarCram::cram(indices.as_slice());
+
+                bincode::serialize(&(table, indices)).unwrap()
+            }
+            Compression::Lz4 => lz4_flex::compress_prepend_size(columns(messages).as_slice()),
+        }
+    }
+
+    /// Decodes exactly `count` messages from `bytes`, encoded with the
+    /// compression identified by `header`.
+    pub(in crate::batch) fn decode(
+        header: u8,
+        bytes: &[u8],
+        count: usize,
+    ) -> Result<Vec<Message>, Top<BatchError>> {
+        match Compression::from_header(header)? {
+            Compression::Plain => rows(bytes, count),
+            Compression::Dictionary => {
+                let (table, indices) = bincode::deserialize::<(Vec<u8>, VarCram)>(bytes)
+                    .map_err(|_| BatchError::MalformedMessages.into_top())
+                    .spot(here!())?;
+
+                let table = rows(table.as_slice(), table.len() / MESSAGE_SIZE)?;
+
+                let indices = indices
+                    .uncram()
+                    .filter(|indices| indices.len() == count)
+                    .ok_or_else(|| BatchError::MalformedMessages.into_top())
+                    .spot(here!())?;
+
+                indices
+                    .into_iter()
+                    .map(|index| {
+                        table
+                            .get(index as usize)
+                            .copied()
+                            .ok_or_else(|| BatchError::MalformedMessages.into_top())
+                            .spot(here!())
+                    })
+                    .collect()
+            }
+            Compression::Lz4 => {
+                // The decompressed size is attacker-controlled: check it
+                // before `lz4_flex` allocates a buffer for it
+                let size = bytes
+                    .get(0..4)
+                    .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize);
+
+                if size != Some(count * MESSAGE_SIZE) {
+                    return BatchError::MalformedMessages.fail().spot(here!());
+                }
+
+                let columns = lz4_flex::decompress_size_prepended(bytes)
+                    .map_err(|_| BatchError::MalformedMessages.into_top())
+                    .spot(here!())?;
+
+                rows(columns.as_slice(), count)
+            }
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Plain
+    }
+}
+
+fn columns(messages: &[Message]) -> Vec<u8> {
+    let mut columns = vec![0u8; messages.len() * MESSAGE_SIZE];
+
+    for (row, message) in messages.iter().enumerate() {
+        for (column, byte) in message.iter().enumerate() {
+            columns[column * messages.len() + row] = *byte;
+        }
+    }
+
+    columns
+}
+
+fn rows(columns: &[u8], count: usize) -> Result<Vec<Message>, Top<BatchError>> {
+    check_columns(columns, count)?;
+
+    let messages = (0..count)
+        .map(|row| message_at(columns, count, row))
+        .collect();
+
+    Ok(messages)
+}
+
+/// Reads the `row`-th of `count` messages laid out in `columns`, in place.
+pub(in crate::batch) fn message_at(columns: &[u8], count: usize, row: usize) -> Message {
+    let mut message = [0u8; MESSAGE_SIZE];
+
+    for (column, byte) in message.iter_mut().enumerate() {
+        *byte = columns[column * count + row];
+    }
+
+    message
+}
+
+/// Returns how many messages `columns` holds.
+pub(in crate::batch) fn column_count(columns: &[u8]) -> Result<usize, Top<BatchError>> {
+    if columns.len() % MESSAGE_SIZE != 0 {
+        return BatchError::MalformedMessages.fail().spot(here!());
+    }
+
+    Ok(columns.len() / MESSAGE_SIZE)
+}
+
+/// Checks that `columns` holds exactly `count` messages.
+fn check_columns(columns: &[u8], count: usize) -> Result<(), Top<BatchError>> {
+    let messages = column_count(columns)?;
+
+    if messages != count {
+        return BatchError::LengthMismatch {
+            ids: count,
+            messages,
+        }
+        .fail()
+        .spot(here!());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::prelude::*;
+
+    const COMPRESSIONS: [Compression; 3] = [
+        Compression::Plain,
+        Compression::Dictionary,
+        Compression::Lz4,
+    ];
+
+    #[test]
+    fn round_trip() {
+        let mut rng = thread_rng();
+
+        for _ in 0..200 {
+            let distinct = rng.gen_range(1..32);
+            let table = (0..distinct).map(|_| rng.gen()).collect::<Vec<Message>>();
+
+            let messages = (0..rng.gen_range(0..1024))
+                .map(|_| *table.choose(&mut rng).unwrap())
+                .collect::<Vec<_>>();
+
+            for compression in COMPRESSIONS {
+                let bytes = compression.encode(messages.as_slice());
+
+                let decoded =
+                    Compression::decode(compression.header(), bytes.as_slice(), messages.len())
+                        .unwrap();
+
+                assert_eq!(decoded, messages);
+
+                assert!(Compression::decode(
+                    compression.header(),
+                    bytes.as_slice(),
+                    messages.len() + 1
+                )
+                .is_err());
+            }
+        }
+    }
+}
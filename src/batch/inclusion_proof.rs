@@ -0,0 +1,34 @@
+use crate::batch::{BatchError, Payload};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::primitives::hash::Hash;
+
+use zebra::vector::Proof;
+
+/// Proves that a `Payload` belongs to the `Batch` with a given root. Carries
+/// the whole Merkle leaf the payload belongs to: its size grows linearly with
+/// `Batch::width`, while the Merkle path shrinks logarithmically.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InclusionProof {
+    leaf: Vec<Payload>,
+    proof: Proof,
+}
+
+impl InclusionProof {
+    pub(in crate::batch) fn new(leaf: Vec<Payload>, proof: Proof) -> Self {
+        InclusionProof { leaf, proof }
+    }
+
+    pub fn verify(&self, root: Hash, payload: &Payload) -> Result<(), Top<BatchError>> {
+        if !self.leaf.contains(payload) {
+            return BatchError::InclusionProofInvalid.fail().spot(here!());
+        }
+
+        self.proof
+            .verify(root, &self.leaf)
+            .pot(BatchError::InclusionProofInvalid, here!())
+    }
+}
@@ -1,14 +1,23 @@
 mod batch;
+mod batch_builder;
+mod batch_report;
 mod broadcast_statement;
 mod compressed_batch;
+mod compressed_batch_view;
+mod compression;
+mod inclusion_proof;
 mod message;
 mod payload;
 mod reduction_statement;
 
-pub(crate) use broadcast_statement::BroadcastStatement;
-pub(crate) use reduction_statement::ReductionStatement;
-
-pub use batch::{Batch, BatchError};
+pub use batch::{Batch, BatchError, DEFAULT_WIDTH};
+pub use batch_builder::{BatchBuilder, SealedBatch};
+pub use batch_report::BatchReport;
+pub use broadcast_statement::BroadcastStatement;
 pub use compressed_batch::CompressedBatch;
+pub use compressed_batch_view::CompressedBatchView;
+pub use compression::Compression;
+pub use inclusion_proof::InclusionProof;
 pub use message::Message;
 pub use payload::Payload;
+pub use reduction_statement::ReductionStatement;
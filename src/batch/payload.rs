@@ -1,9 +1,28 @@
-use crate::batch::Message;
+use crate::batch::{batch::NULL_ID, Message};
 
 use serde::{Deserialize, Serialize};
 
+/// The `sequence`-th message `id` submits. A client can contribute several
+/// payloads to the same batch, provided their `sequence`s are distinct.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Payload {
     pub id: u64,
+    pub sequence: u64,
     pub message: Message,
 }
+
+impl Payload {
+    /// The `(id, sequence)` pair by which payloads are sorted in a batch.
+    pub fn key(&self) -> (u64, u64) {
+        (self.id, self.sequence)
+    }
+
+    /// Fills the last Merkle leaf of a batch up to its width.
+    pub(in crate::batch) fn padding() -> Self {
+        Payload {
+            id: NULL_ID,
+            sequence: u64::MAX,
+            message: [u8::MAX; 8],
+        }
+    }
+}
@@ -1,15 +1,20 @@
-use crate::crypto::Header;
+use crate::crypto::{Domain, Header};
 
 use serde::Serialize;
 
 use talk::crypto::{primitives::hash::Hash, Statement};
 
+/// Multi-signed by the clients in a batch with a given root, in place of
+/// individual `BroadcastStatement`s (see `SealedBatch::finalize`).
 #[derive(Debug, Clone, Serialize)]
-pub(crate) struct ReductionStatement(Hash);
+pub struct ReductionStatement {
+    domain: Domain,
+    root: Hash,
+}
 
 impl ReductionStatement {
-    pub fn new(root: Hash) -> Self {
-        ReductionStatement(root)
+    pub fn new(domain: Domain, root: Hash) -> Self {
+        ReductionStatement { domain, root }
     }
 }
 
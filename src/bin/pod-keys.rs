@@ -0,0 +1,467 @@
+use clap::{ArgEnum, Args, Parser, Subcommand};
+
+use pod::{
+    Directory, Domain, FaultModel, KdfParameters, Keystore, Membership, Passepartout, Secret,
+    ThresholdKeyChain, ThresholdKeys,
+};
+
+use rand::prelude::*;
+
+use rand_chacha::ChaCha20Rng;
+
+use std::{collections::HashSet, fmt::Debug, fs, path::PathBuf, process};
+
+use talk::crypto::KeyCard;
+
+#[derive(Parser)]
+#[clap(name = "pod-keys", about = "Generate and inspect pod key material")]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a passepartout and split it into membership, directory and
+    /// per-server keychain files
+    Generate {
+        /// Total number of keychains (servers and clients)
+        #[clap(long)]
+        size: usize,
+        /// Number of keychains assigned to servers
+        #[clap(long)]
+        servers: usize,
+        /// Derive all keychains deterministically from this seed
+        #[clap(long)]
+        seed: Option<u64>,
+        /// Faults the membership tolerates
+        #[clap(long, arg_enum, default_value = "byzantine")]
+        fault_model: FaultModelArg,
+        /// Deployment all statements are bound to (e.g., distinct for test
+        /// and production clusters)
+        #[clap(long, default_value = "0")]
+        deployment: u64,
+        /// Epoch all statements are bound to
+        #[clap(long, default_value = "0")]
+        epoch: u64,
+        /// Deal threshold signature keys, and write (unencrypted) per-server
+        /// key shares
+        #[clap(long)]
+        threshold: bool,
+        /// Directory where all files are written
+        #[clap(long)]
+        output: PathBuf,
+        #[clap(flatten)]
+        secret: SecretArgs,
+    },
+    /// Print a summary of membership, directory and passepartout files
+    Inspect {
+        #[clap(flatten)]
+        files: FileArgs,
+    },
+    /// Check that membership, directory and passepartout files are consistent
+    Verify {
+        #[clap(flatten)]
+        files: FileArgs,
+        /// Directory containing per-server keychain files, as written by `generate`
+        #[clap(long)]
+        servers: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum FaultModelArg {
+    /// Byzantine faults, n >= 3f + 1
+    Byzantine,
+    /// Byzantine faults, n >= 5f + 1
+    FastPath,
+    /// Crash faults only, n >= 2f + 1
+    Crash,
+}
+
+#[derive(Args)]
+struct SecretArgs {
+    /// Encrypt passepartout and server keychains with this password
+    #[clap(long, conflicts_with = "key_file")]
+    password: Option<String>,
+    /// Encrypt passepartout and server keychains with the content of this file
+    #[clap(long)]
+    key_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct FileArgs {
+    #[clap(long)]
+    membership: Option<PathBuf>,
+    #[clap(long)]
+    directory: Option<PathBuf>,
+    /// Plaintext passepartout file, or keystore if a secret is provided
+    #[clap(long)]
+    passepartout: Option<PathBuf>,
+    #[clap(flatten)]
+    secret: SecretArgs,
+}
+
+const MEMBERSHIP: &str = "membership.bin";
+const DIRECTORY: &str = "directory.bin";
+const INDEXED_DIRECTORY: &str = "directory.idx";
+const PASSEPARTOUT: &str = "passepartout.bin";
+const KEYSTORE: &str = "passepartout.keys";
+const SERVERS: &str = "servers";
+
+fn main() {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Generate {
+            size,
+            servers,
+            seed,
+            fault_model,
+            deployment,
+            epoch,
+            threshold,
+            output,
+            secret,
+        } => generate(
+            size,
+            servers,
+            seed,
+            fault_model.into(),
+            Domain::new(deployment, epoch),
+            threshold,
+            output,
+            secret.secret(),
+        ),
+        Command::Inspect { files } => inspect(&files),
+        Command::Verify { files, servers } => verify(&files, servers),
+    }
+}
+
+impl From<FaultModelArg> for FaultModel {
+    fn from(fault_model: FaultModelArg) -> Self {
+        match fault_model {
+            FaultModelArg::Byzantine => FaultModel::Byzantine,
+            FaultModelArg::FastPath => FaultModel::ByzantineFastPath,
+            FaultModelArg::Crash => FaultModel::Crash,
+        }
+    }
+}
+
+impl SecretArgs {
+    fn secret(&self) -> Option<Secret> {
+        match (&self.password, &self.key_file) {
+            (Some(password), _) => Some(Secret::Password(password.clone())),
+            (None, Some(key_file)) => Some(Secret::KeyFile(key_file.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn generate(
+    size: usize,
+    servers: usize,
+    seed: Option<u64>,
+    fault_model: FaultModel,
+    domain: Domain,
+    threshold: bool,
+    output: PathBuf,
+    secret: Option<Secret>,
+) {
+    if servers > size {
+        abort("`--servers` cannot exceed `--size`");
+    }
+
+    let passepartout = match seed {
+        Some(seed) => Passepartout::from_seed(seed, size),
+        None => Passepartout::random(size),
+    };
+
+    let (membership, directory) = passepartout.system(servers);
+    let membership = membership.with_fault_model(fault_model).with_domain(domain);
+
+    fs::create_dir_all(&output).unwrap_or_else(|error| exit(error));
+
+    let membership = if threshold {
+        let mut rng = match seed {
+            Some(seed) => {
+                // Keychains use streams starting from 0 (see `Passepartout::from_seed`)
+                let mut rng = ChaCha20Rng::seed_from_u64(seed);
+                rng.set_stream(u64::MAX);
+                rng
+            }
+            None => ChaCha20Rng::from_entropy(),
+        };
+
+        let (keys, keychains) =
+            ThresholdKeys::deal(&membership, &mut rng).unwrap_or_else(|error| exit(error));
+
+        let servers_path = output.join(SERVERS);
+        fs::create_dir_all(&servers_path).unwrap_or_else(|error| exit(error));
+
+        for (index, keychain) in keychains.iter().enumerate() {
+            keychain
+                .save(servers_path.join(format!("server-{}.threshold", index)))
+                .unwrap_or_else(|error| exit(error));
+        }
+
+        membership.with_threshold_keys(keys)
+    } else {
+        membership
+    };
+
+    membership
+        .save(output.join(MEMBERSHIP))
+        .unwrap_or_else(|error| exit(error));
+
+    directory
+        .save(output.join(DIRECTORY))
+        .unwrap_or_else(|error| exit(error));
+
+    directory
+        .save_indexed(output.join(INDEXED_DIRECTORY))
+        .unwrap_or_else(|error| exit(error));
+
+    match secret {
+        Some(secret) => {
+            passepartout
+                .save_encrypted(output.join(KEYSTORE), &secret)
+                .unwrap_or_else(|error| exit(error));
+
+            let servers_path = output.join(SERVERS);
+            fs::create_dir_all(&servers_path).unwrap_or_else(|error| exit(error));
+
+            for (index, identity) in membership.servers().keys().enumerate() {
+                let keychain = passepartout.keychain(*identity);
+
+                Keystore::save(
+                    servers_path.join(format!("server-{}.keys", index)),
+                    [&keychain],
+                    &secret,
+                    KdfParameters::random(),
+                )
+                .unwrap_or_else(|error| exit(error));
+            }
+        }
+        None => passepartout
+            .save(output.join(PASSEPARTOUT))
+            .unwrap_or_else(|error| exit(error)),
+    }
+
+    summarize_membership(&membership);
+    summarize_directory(&directory);
+    summarize_passepartout(&passepartout);
+}
+
+fn inspect(files: &FileArgs) {
+    let (membership, directory, passepartout) = files.load();
+
+    if let Some(membership) = membership.as_ref() {
+        summarize_membership(membership);
+    }
+
+    if let Some(directory) = directory.as_ref() {
+        summarize_directory(directory);
+    }
+
+    if let Some(passepartout) = passepartout.as_ref() {
+        summarize_passepartout(passepartout);
+    }
+}
+
+fn verify(files: &FileArgs, servers: Option<PathBuf>) {
+    let (membership, directory, passepartout) = files.load();
+    let mut errors = 0;
+
+    if let Some(directory) = directory.as_ref() {
+        for id in 0..(directory.capacity() as u64) {
+            if let Err(error) = directory.try_keycard(id) {
+                println!("Client {} has a corrupted entry: {:?}", id, error);
+                errors += 1;
+            }
+        }
+    }
+
+    if let (Some(membership), Some(directory)) = (membership.as_ref(), directory.as_ref()) {
+        let servers = membership.servers().keys().collect::<HashSet<_>>();
+
+        for id in 0..(directory.capacity() as u64) {
+            if let Ok(Some(keycard)) = directory.try_keycard(id) {
+                if servers.contains(&keycard.identity()) {
+                    println!("Client {} is also a server", id);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    if let Some(passepartout) = passepartout.as_ref() {
+        if let Some(membership) = membership.as_ref() {
+            for (identity, keycard) in membership.servers() {
+                if !passepartout.contains(*identity) {
+                    println!("Server {:?} missing from passepartout", identity);
+                    errors += 1;
+                } else if !same_keycard(&passepartout.keychain(*identity).keycard(), keycard) {
+                    println!("Server {:?} has a mismatching keycard", identity);
+                    errors += 1;
+                }
+            }
+        }
+
+        if let Some(directory) = directory.as_ref() {
+            for id in 0..(directory.capacity() as u64) {
+                if let Ok(Some(keycard)) = directory.try_keycard(id) {
+                    if !passepartout.contains(keycard.identity()) {
+                        println!("Client {} missing from passepartout", id);
+                        errors += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if let (Some(membership), Some(servers)) = (membership.as_ref(), servers.as_ref()) {
+        if membership.threshold_keys().is_some() {
+            for index in 0..membership.servers().len() {
+                let path = servers.join(format!("server-{}.threshold", index));
+
+                match ThresholdKeyChain::load(&path) {
+                    Ok(keychain) if keychain.matches(membership) => {}
+                    Ok(_) => {
+                        println!("{:?} has mismatching threshold key shares", path);
+                        errors += 1;
+                    }
+                    Err(error) => {
+                        println!("{:?}: {:?}", path, error);
+                        errors += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // `generate` only writes (encrypted) server keychains when given a secret
+    let secret = match (files.secret.secret(), servers.as_ref()) {
+        (None, Some(servers)) if servers.join("server-0.keys").exists() => {
+            abort("server keychains require `--password` or `--key-file`")
+        }
+        (secret, _) => secret,
+    };
+
+    if let (Some(membership), Some(servers), Some(secret)) =
+        (membership.as_ref(), servers.as_ref(), secret.as_ref())
+    {
+        for (index, (identity, keycard)) in membership.servers().iter().enumerate() {
+            let path = servers.join(format!("server-{}.keys", index));
+
+            match Keystore::open(&path, secret).and_then(|keystore| keystore.keychain(*identity)) {
+                Ok(keychain) if same_keycard(&keychain.keycard(), keycard) => {}
+                Ok(_) => {
+                    println!("{:?} has a mismatching keycard", path);
+                    errors += 1;
+                }
+                Err(error) => {
+                    println!("{:?}: {:?}", path, error);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    if errors > 0 {
+        abort(format!("{} inconsistencies found", errors).as_str());
+    }
+
+    println!("All files are consistent");
+}
+
+// Compares all public keys, not just identities
+fn same_keycard(left: &KeyCard, right: &KeyCard) -> bool {
+    bincode::serialize(left).unwrap() == bincode::serialize(right).unwrap()
+}
+
+impl FileArgs {
+    fn load(&self) -> (Option<Membership>, Option<Directory>, Option<Passepartout>) {
+        let membership = self
+            .membership
+            .as_ref()
+            .map(|path| Membership::load(path).unwrap_or_else(|error| exit(error)));
+
+        let directory = self.directory.as_ref().map(|path| {
+            if path.extension().map(|extension| extension == "idx") == Some(true) {
+                Directory::map(path).unwrap_or_else(|error| exit(error))
+            } else {
+                Directory::load(path).unwrap_or_else(|error| exit(error))
+            }
+        });
+
+        let passepartout =
+            self.passepartout
+                .as_ref()
+                .map(|path| match self.secret.secret() {
+                    Some(secret) => Passepartout::load_encrypted(path, &secret)
+                        .unwrap_or_else(|error| exit(error)),
+                    None => Passepartout::load(path).unwrap_or_else(|error| exit(error)),
+                });
+
+        (membership, directory, passepartout)
+    }
+}
+
+fn summarize_membership(membership: &Membership) {
+    println!("Membership");
+    println!("  Servers: {}", membership.servers().len());
+    println!("  Deployment: {}", membership.domain().deployment());
+    println!("  Epoch: {}", membership.domain().epoch());
+    println!("  Fault model: {:?}", membership.fault_model());
+    println!("  Plurality: {}", membership.plurality());
+    println!("  Quorum: {}", membership.quorum());
+    println!(
+        "  Threshold keys: {}",
+        if membership.threshold_keys().is_some() {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+
+    println!("  Total weight: {}", membership.total_weight());
+
+    for (index, identity) in membership.servers().keys().enumerate() {
+        println!(
+            "  [{}] {:?} (weight {})",
+            index,
+            identity,
+            membership.weight(*identity)
+        );
+    }
+}
+
+fn summarize_directory(directory: &Directory) {
+    let occupied = (0..(directory.capacity() as u64))
+        .filter(|id| matches!(directory.try_keycard(*id), Ok(Some(_))))
+        .count();
+
+    println!("Directory");
+    println!("  Capacity: {}", directory.capacity());
+    println!("  Clients: {}", occupied);
+}
+
+fn summarize_passepartout(passepartout: &Passepartout) {
+    println!("Passepartout");
+    println!("  Keychains: {}", passepartout.len());
+}
+
+fn exit<E>(error: E) -> !
+where
+    E: Debug,
+{
+    eprintln!("Error: {:?}", error);
+    process::exit(1)
+}
+
+fn abort(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    process::exit(1)
+}
@@ -1,6 +1,11 @@
 use crate::{
+    crypto::Domain,
+    dispersal::{Dispersal, DispersalStatement, Fragment},
     membership::{Certificate, Membership},
-    server::WitnessStatement,
+    server::{
+        Equivocation, EquivocationDetector, OrderStatement, Receipt, ReceiptStatement, Request,
+        WitnessStatement,
+    },
 };
 
 use doomstack::{here, Doom, ResultExt, Top};
@@ -9,7 +14,11 @@ use futures::stream::{FuturesUnordered, StreamExt};
 
 use rand::prelude::*;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use talk::{
     crypto::{
@@ -21,24 +30,60 @@ use talk::{
     time::{sleep_schedules::CappedExponential, SleepSchedule},
 };
 
-use tokio::sync::{
-    oneshot::{self, Sender as OneshotSender},
-    watch::{self, Receiver as WatchReceiver},
+use tokio::{
+    sync::{
+        oneshot::{self, Sender as OneshotSender},
+        watch::{self, Receiver as WatchReceiver},
+    },
+    time,
 };
 
+const RECEIPT_POLL: Duration = Duration::from_millis(500);
+
 pub struct LoadBroker {
     membership: Arc<Membership>,
     connector: Arc<SessionConnector>,
-    batches: Arc<Vec<(Hash, Vec<u8>)>>,
+    load: Arc<Load>,
+    detector: Arc<Mutex<EquivocationDetector>>,
     fuse: Fuse,
 }
 
+enum Load {
+    // Every server receives every batch whole
+    Batches(Vec<(Hash, Vec<u8>)>),
+    // Every server receives one fragment of every batch (root, commitment
+    // and fragments)
+    Dispersed(Vec<(Hash, Hash, Vec<Fragment>)>),
+}
+
+#[derive(Doom)]
+pub enum LoadBrokerError {
+    #[doom(description("Not enough witness shards ({} of {})", power, threshold))]
+    WitnessIncomplete { power: u64, threshold: u64 },
+    #[doom(description("Not enough receipt shards for any height"))]
+    ReceiptIncomplete,
+}
+
 #[derive(Doom)]
 enum TrySubmitError {
     #[doom(description("Failed to connect."))]
     ConnectFailed,
     #[doom(description("Connection error"))]
     ConnectionError,
+    #[doom(description("Witness shard invalid"))]
+    WitnessShardInvalid,
+    #[doom(description("Order shard invalid"))]
+    OrderShardInvalid,
+}
+
+#[derive(Doom)]
+enum TryCollectReceiptError {
+    #[doom(description("Failed to connect."))]
+    ConnectFailed,
+    #[doom(description("Connection error"))]
+    ConnectionError,
+    #[doom(description("Receipt shard invalid"))]
+    ReceiptShardInvalid,
 }
 
 impl LoadBroker {
@@ -47,35 +92,93 @@ impl LoadBroker {
         connector: SessionConnector,
         batches: Vec<(Hash, Vec<u8>)>,
     ) -> Self {
+        LoadBroker::with_load(membership, connector, Load::Batches(batches))
+    }
+
+    /// Like `new`, but every batch is erasure-coded (see `Dispersal`): each
+    /// server receives a single fragment, cutting broker egress from `n`
+    /// times the size of a batch to roughly `n / plurality` times.
+    pub fn dispersed(
+        membership: Membership,
+        connector: SessionConnector,
+        batches: Vec<(Hash, Vec<u8>)>,
+    ) -> Self {
+        let dispersal = Dispersal::new(&membership);
+
+        let batches = batches
+            .into_iter()
+            .map(|(root, batch)| {
+                let (commitment, fragments) = dispersal.encode(batch.as_slice());
+                (root, commitment, fragments)
+            })
+            .collect();
+
+        LoadBroker::with_load(membership, connector, Load::Dispersed(batches))
+    }
+
+    fn with_load(membership: Membership, connector: SessionConnector, load: Load) -> Self {
         let membership = Arc::new(membership);
         let connector = Arc::new(connector);
-        let batches = Arc::new(batches);
+        let load = Arc::new(load);
+        let detector = Arc::new(Mutex::new(EquivocationDetector::new()));
         let fuse = Fuse::new();
 
         LoadBroker {
             membership,
             connector,
-            batches,
+            load,
+            detector,
             fuse,
         }
     }
 
-    pub async fn broadcast(&self, index: usize) {
-        let mut verifiers = self
-            .membership
-            .servers()
-            .keys()
-            .copied()
-            .choose_multiple(&mut thread_rng(), self.membership.plurality());
+    /// Fails if the servers asked to witness the batch stop trying before
+    /// enough witness shards are collected (e.g., if `self` is shutting down).
+    pub async fn broadcast(&self, index: usize) -> Result<(), Top<LoadBrokerError>> {
+        // Whole batches are verified by a plurality of servers. Fragments
+        // cannot be verified: every server witnesses storing its fragment,
+        // and a quorum of witnesses is needed
+        let (mut verifiers, threshold) = match self.load.as_ref() {
+            Load::Batches(_) => {
+                let mut servers = self
+                    .membership
+                    .servers()
+                    .keys()
+                    .copied()
+                    .collect::<Vec<_>>();
+
+                servers.shuffle(&mut thread_rng());
+
+                // Pick random servers until they reach a plurality of the weight
+                let mut power = 0;
+
+                let verifiers = servers
+                    .into_iter()
+                    .take_while(|server| {
+                        let reached = power >= self.membership.plurality();
+                        power += self.membership.weight(*server);
+                        !reached
+                    })
+                    .collect();
+
+                (verifiers, self.membership.plurality())
+            }
+            Load::Dispersed(_) => (
+                self.membership.servers().keys().copied().collect(),
+                self.membership.quorum(),
+            ),
+        };
 
         verifiers.sort();
 
         let mut witness_shard_receivers = Vec::new();
         let (witness_sender, witness_receiver) = watch::channel(None);
 
-        for (identity, keycard) in self.membership.servers() {
+        for (position, (identity, keycard)) in self.membership.servers().iter().enumerate() {
+            let domain = self.membership.domain();
             let connector = self.connector.clone();
-            let batches = self.batches.clone();
+            let load = self.load.clone();
+            let detector = self.detector.clone();
             let keycard = keycard.clone();
 
             let witness_shard_sender = verifiers.binary_search(identity).ok().map(|_| {
@@ -89,9 +192,12 @@ impl LoadBroker {
 
             self.fuse.spawn(async move {
                 LoadBroker::submit(
+                    domain,
                     connector,
-                    batches,
+                    load,
+                    detector,
                     index,
+                    position,
                     keycard,
                     witness_shard_sender,
                     witness_receiver,
@@ -100,24 +206,202 @@ impl LoadBroker {
             });
         }
 
-        let witness_shard_receivers = witness_shard_receivers
+        let mut witness_shard_receivers = witness_shard_receivers
             .into_iter()
             .collect::<FuturesUnordered<_>>();
 
-        let witness_shards = witness_shard_receivers
-            .map(|shard| shard.unwrap())
-            .collect::<Vec<_>>()
-            .await;
+        let mut witness_shards = Vec::new();
+        let mut power = 0;
+
+        while power < threshold {
+            // Servers are retried until they answer (see `submit`): a sender
+            // is only dropped if its task is cancelled, and its shard skipped
+            match witness_shard_receivers.next().await {
+                Some(Ok((identity, shard))) => {
+                    power += self.membership.weight(identity);
+                    witness_shards.push((identity, shard));
+                }
+                Some(Err(_)) => {}
+                None => {
+                    return LoadBrokerError::WitnessIncomplete { power, threshold }
+                        .fail()
+                        .spot(here!())
+                }
+            }
+        }
 
         let witness = Certificate::aggregate(self.membership.as_ref(), witness_shards);
 
         let _ = witness_sender.send(Some(witness));
+
+        Ok(())
+    }
+
+    /// Waits for a quorum of servers to deliver the `index`-th batch, and
+    /// aggregates their receipts. Servers only keep receipts for the last
+    /// thousand or so batches they delivered.
+    ///
+    /// Fails if every server answered, but no height gathered a quorum of
+    /// receipt shards (i.e., more servers than tolerated are faulty).
+    pub async fn receipt(&self, index: usize) -> Result<Receipt, Top<LoadBrokerError>> {
+        let domain = self.membership.domain();
+        let root = self.load.root(index);
+
+        let mut receipt_shards = self
+            .membership
+            .servers()
+            .values()
+            .map(|keycard| {
+                LoadBroker::collect_receipt(domain, self.connector.as_ref(), root, keycard)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        // Correct servers agree on the height of every batch, faulty ones
+        // might sign any height
+        let mut heights = HashMap::<u64, (u64, Vec<(Identity, MultiSignature)>)>::new();
+
+        while let Some((identity, height, shard)) = receipt_shards.next().await {
+            let caught = {
+                let mut detector = self.detector.lock().unwrap();
+                detector.observe(identity, height, root, shard);
+                detector.caught(identity)
+            };
+
+            // Servers excluded for equivocating no longer count towards receipts
+            if caught {
+                continue;
+            }
+
+            let (power, shards) = heights.entry(height).or_default();
+            *power += self.membership.weight(identity);
+            shards.push((identity, shard));
+
+            if *power >= self.membership.quorum() {
+                let certificate =
+                    Certificate::aggregate_quorum(self.membership.as_ref(), shards.drain(..));
+
+                return Ok(Receipt::new(height, root, certificate));
+            }
+        }
+
+        LoadBrokerError::ReceiptIncomplete.fail().spot(here!())
+    }
+
+    /// Proofs of misbehaviour for every server caught signing conflicting
+    /// receipts (see `receipt`) or order shards (see `broadcast`) so far.
+    pub fn equivocations(&self) -> Vec<Equivocation> {
+        self.detector
+            .lock()
+            .unwrap()
+            .equivocations()
+            .cloned()
+            .collect()
+    }
+
+    /// Submits `equivocation` to one server after another (in random order,
+    /// skipping servers caught equivocating), until one acknowledges it and
+    /// orders it: once delivered, every correct server excludes the culprit.
+    /// A faulty server might acknowledge without ordering: if the culprit
+    /// does not get excluded, `report` can be called again. Returns `false`
+    /// if no server acknowledged.
+    pub async fn report(&self, equivocation: &Equivocation) -> bool {
+        let mut servers = {
+            let detector = self.detector.lock().unwrap();
+
+            self.membership
+                .servers()
+                .keys()
+                .copied()
+                .filter(|server| *server != equivocation.identity() && !detector.caught(*server))
+                .collect::<Vec<_>>()
+        };
+
+        servers.shuffle(&mut thread_rng());
+
+        for server in servers {
+            if LoadBroker::try_report(self.connector.as_ref(), server, equivocation)
+                .await
+                .is_some()
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    async fn try_report(
+        connector: &SessionConnector,
+        server: Identity,
+        equivocation: &Equivocation,
+    ) -> Option<()> {
+        let mut session = connector.connect(server).await.ok()?;
+
+        session.send_raw(&Request::Report).await.ok()?;
+        session.send_raw(equivocation).await.ok()?;
+        session.receive_raw::<()>().await.ok()?;
+
+        session.end();
+        Some(())
+    }
+
+    async fn collect_receipt(
+        domain: Domain,
+        connector: &SessionConnector,
+        root: Hash,
+        server: &KeyCard,
+    ) -> (Identity, u64, MultiSignature) {
+        loop {
+            match LoadBroker::try_collect_receipt(domain, connector, root, server).await {
+                Ok(Some((height, shard))) => return (server.identity(), height, shard),
+                Ok(None) => {} // The server did not deliver the batch yet
+                Err(error) => println!("{:?}", error),
+            }
+
+            time::sleep(RECEIPT_POLL).await;
+        }
     }
 
+    async fn try_collect_receipt(
+        domain: Domain,
+        connector: &SessionConnector,
+        root: Hash,
+        server: &KeyCard,
+    ) -> Result<Option<(u64, MultiSignature)>, Top<TryCollectReceiptError>> {
+        let mut session = connector
+            .connect(server.identity())
+            .await
+            .pot(TryCollectReceiptError::ConnectFailed, here!())?;
+
+        session
+            .send_raw(&Request::Receipt { root })
+            .await
+            .pot(TryCollectReceiptError::ConnectionError, here!())?;
+
+        let receipt = session
+            .receive_raw::<Option<(u64, MultiSignature)>>()
+            .await
+            .pot(TryCollectReceiptError::ConnectionError, here!())?;
+
+        session.end();
+
+        if let Some((height, shard)) = receipt {
+            shard
+                .verify([server], &ReceiptStatement::new(domain, height, root))
+                .pot(TryCollectReceiptError::ReceiptShardInvalid, here!())?;
+        }
+
+        Ok(receipt)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn submit(
+        domain: Domain,
         connector: Arc<SessionConnector>,
-        batches: Arc<Vec<(Hash, Vec<u8>)>>,
+        load: Arc<Load>,
+        detector: Arc<Mutex<EquivocationDetector>>,
         index: usize,
+        position: usize,
         server: KeyCard,
         mut witness_shard_sender: Option<OneshotSender<(Identity, MultiSignature)>>,
         mut witness_receiver: WatchReceiver<Option<Certificate>>,
@@ -132,9 +416,12 @@ impl LoadBroker {
 
         loop {
             if let Err(error) = LoadBroker::try_submit(
+                domain,
                 connector.as_ref(),
-                batches.as_ref(),
+                load.as_ref(),
+                detector.as_ref(),
                 index,
+                position,
                 &server,
                 &mut witness_shard_sender,
                 &mut witness_receiver,
@@ -150,10 +437,14 @@ impl LoadBroker {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn try_submit(
+        domain: Domain,
         connector: &SessionConnector,
-        batches: &Vec<(Hash, Vec<u8>)>,
+        load: &Load,
+        detector: &Mutex<EquivocationDetector>,
         index: usize,
+        position: usize,
         server: &KeyCard,
         witness_shard_sender: &mut Option<OneshotSender<(Identity, MultiSignature)>>,
         witness_receiver: &mut WatchReceiver<Option<Certificate>>,
@@ -163,38 +454,65 @@ impl LoadBroker {
             .await
             .pot(TrySubmitError::ConnectFailed, here!())?;
 
-        let (root, batch) = batches.get(index).unwrap();
-        let root = *root;
-
-        session
-            .send_raw_bytes(batch.as_ref())
-            .await
-            .pot(TrySubmitError::ConnectionError, here!())?;
-
-        if witness_shard_sender.is_some() {
-            session
-                .send_raw(&true)
-                .await
-                .pot(TrySubmitError::ConnectionError, here!())?;
-
-            let witness_shard = session
-                .receive_raw::<MultiSignature>()
-                .await
-                .pot(TrySubmitError::ConnectionError, here!())?;
-
-            witness_shard
-                .verify([server], &WitnessStatement::new(root))
-                .unwrap();
-
-            let _ = witness_shard_sender
-                .take()
-                .unwrap()
-                .send((server.identity(), witness_shard));
-        } else {
-            session
-                .send_raw(&false)
-                .await
-                .pot(TrySubmitError::ConnectionError, here!())?;
+        match load {
+            Load::Batches(batches) => {
+                let (root, batch) = batches.get(index).unwrap();
+                let verify = witness_shard_sender.is_some();
+
+                session
+                    .send_raw(&Request::Batch { verify })
+                    .await
+                    .pot(TrySubmitError::ConnectionError, here!())?;
+
+                session
+                    .send_raw_bytes(batch.as_ref())
+                    .await
+                    .pot(TrySubmitError::ConnectionError, here!())?;
+
+                if verify {
+                    let witness_shard = session
+                        .receive_raw::<MultiSignature>()
+                        .await
+                        .pot(TrySubmitError::ConnectionError, here!())?;
+
+                    witness_shard
+                        .verify([server], &WitnessStatement::new(domain, *root))
+                        .pot(TrySubmitError::WitnessShardInvalid, here!())?;
+
+                    let _ = witness_shard_sender
+                        .take()
+                        .unwrap()
+                        .send((server.identity(), witness_shard));
+                }
+            }
+            Load::Dispersed(batches) => {
+                let (_, commitment, fragments) = batches.get(index).unwrap();
+
+                session
+                    .send_raw(&Request::Fragment)
+                    .await
+                    .pot(TrySubmitError::ConnectionError, here!())?;
+
+                session
+                    .send_raw(&(*commitment, &fragments[position]))
+                    .await
+                    .pot(TrySubmitError::ConnectionError, here!())?;
+
+                let witness_shard = session
+                    .receive_raw::<MultiSignature>()
+                    .await
+                    .pot(TrySubmitError::ConnectionError, here!())?;
+
+                witness_shard
+                    .verify([server], &DispersalStatement::new(domain, *commitment))
+                    .pot(TrySubmitError::WitnessShardInvalid, here!())?;
+
+                // `witness_shard_sender` is `None` if a previous attempt
+                // already delivered the witness shard
+                if let Some(witness_shard_sender) = witness_shard_sender.take() {
+                    let _ = witness_shard_sender.send((server.identity(), witness_shard));
+                }
+            }
         }
 
         // If `changed()` returns an `Err`, this means that `witness_sender` was
@@ -212,12 +530,42 @@ impl LoadBroker {
             .await
             .pot(TrySubmitError::ConnectionError, here!())?;
 
-        let _order_shard = session
-            .receive_raw::<MultiSignature>()
+        let (slot, order_shard) = session
+            .receive_raw::<(u64, MultiSignature)>()
             .await
             .pot(TrySubmitError::ConnectionError, here!())?;
 
+        // Servers order the commitment of dispersed batches
+        let ordered = load.ordered(index);
+
+        order_shard
+            .verify([server], &OrderStatement::new(domain, slot, ordered))
+            .pot(TrySubmitError::OrderShardInvalid, here!())?;
+
+        // Order shards are retained to catch servers reusing a slot
+        detector
+            .lock()
+            .unwrap()
+            .observe_order(server.identity(), slot, ordered, order_shard);
+
         session.end();
         Ok(())
     }
 }
+
+impl Load {
+    fn root(&self, index: usize) -> Hash {
+        match self {
+            Load::Batches(batches) => batches[index].0,
+            Load::Dispersed(batches) => batches[index].0,
+        }
+    }
+
+    // Digest servers sign order shards for (see `OrderStatement`)
+    fn ordered(&self, index: usize) -> Hash {
+        match self {
+            Load::Batches(batches) => batches[index].0,
+            Load::Dispersed(batches) => batches[index].1,
+        }
+    }
+}
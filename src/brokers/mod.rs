@@ -1,3 +1,3 @@
 mod load_broker;
 
-pub use load_broker::LoadBroker;
+pub use load_broker::{LoadBroker, LoadBrokerError};
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifies a pod deployment and epoch. Every statement binds a `Domain`:
+/// signatures produced in one deployment (or epoch) are invalid in any other,
+/// even if keys are shared (e.g., between test and production clusters).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Domain {
+    deployment: u64,
+    epoch: u64,
+}
+
+impl Domain {
+    pub fn new(deployment: u64, epoch: u64) -> Self {
+        Domain { deployment, epoch }
+    }
+
+    pub fn deployment(&self) -> u64 {
+        self.deployment
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+}
@@ -1,10 +1,78 @@
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[repr(i8)]
-pub(crate) enum Header {
-    Broadcast = 0,
-    Reduction = 1,
-    Witness = 2,
-    Order = 3,
+use serde::Serialize;
+
+/// Separates the signatures of different kinds of statements: every
+/// `talk::crypto::Statement` signs its header along with its content.
+///
+/// Headers are serialized by position: variants are never reordered or
+/// removed, and new ones are only ever appended.
+///
+/// Applications building on pod define their own statements with an
+/// `Application` header. `namespace` must be unique to the application (its
+/// crate name is a natural choice), and `tag` distinguishes the statements
+/// within it: two applications cannot collide, nor collide with pod's own
+/// statements. Application statements should also bind a `Domain`, as pod's
+/// own statements do.
+///
+/// ```ignore
+/// #[derive(Serialize)]
+/// struct VoteStatement {
+///     domain: Domain,
+///     proposal: u64,
+/// }
+///
+/// impl Statement for VoteStatement {
+///     type Header = Header;
+///     const HEADER: Header = Header::Application {
+///         namespace: "my-app",
+///         tag: 0,
+///     };
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Header {
+    Broadcast,
+    Reduction,
+    Witness,
+    Order,
+    Dispersal,
+    Application { namespace: &'static str, tag: u32 },
+    Receipt,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use talk::crypto::{KeyChain, Statement};
+
+    #[derive(Serialize)]
+    struct First(u64);
+
+    #[derive(Serialize)]
+    struct Second(u64);
+
+    impl Statement for First {
+        type Header = Header;
+        const HEADER: Header = Header::Application {
+            namespace: "first",
+            tag: 0,
+        };
+    }
+
+    impl Statement for Second {
+        type Header = Header;
+        const HEADER: Header = Header::Application {
+            namespace: "second",
+            tag: 0,
+        };
+    }
+
+    #[test]
+    fn namespaces() {
+        let keychain = KeyChain::random();
+        let signature = keychain.sign(&First(42)).unwrap();
+
+        signature.verify(&keychain.keycard(), &First(42)).unwrap();
+        assert!(signature.verify(&keychain.keycard(), &Second(42)).is_err());
+    }
 }
@@ -1,3 +1,5 @@
+mod domain;
 mod header;
 
-pub(crate) use header::Header;
+pub use domain::Domain;
+pub use header::Header;
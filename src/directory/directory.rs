@@ -1,17 +1,42 @@
+use crate::{
+    directory::mapped_keycards::{self, MappedKeycards},
+    persistence::{self, FileKind, PersistenceError, LEGACY_VERSION},
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{Deserialize, Deserializer},
+    ser::{Error as _, Serialize, SerializeStruct, Serializer},
+};
 
-use std::fs;
+use std::{path::Path, sync::Arc};
 
 use talk::crypto::KeyCard;
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone)]
 pub struct Directory {
+    keycards: KeyCards,
+}
+
+#[derive(Clone)]
+enum KeyCards {
+    Loaded(Vec<Option<Arc<KeyCard>>>),
+    Mapped(Arc<MappedKeycards>),
+}
+
+// `Directory` serializes as it did before it could be mapped
+#[derive(serde::Deserialize)]
+#[serde(rename = "Directory")]
+struct Serialized {
     keycards: Vec<Option<KeyCard>>,
 }
 
 const CHUNKS: usize = 64;
+const VERSION: u16 = 1;
+const CACHE: usize = 1 << 20;
 
 impl Directory {
     pub fn new() -> Directory {
@@ -19,40 +44,181 @@ impl Directory {
     }
 
     pub(crate) fn from_keycards(keycards: Vec<Option<KeyCard>>) -> Directory {
-        Directory { keycards }
+        let keycards = keycards
+            .into_iter()
+            .map(|keycard| keycard.map(Arc::new))
+            .collect();
+
+        Directory {
+            keycards: KeyCards::Loaded(keycards),
+        }
     }
 
-    pub fn load(path: &str) -> Directory {
-        let bytes = fs::read(path).unwrap();
+    pub fn load<P>(path: P) -> Result<Directory, Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = persistence::read(path)?;
+        let (version, body) = persistence::open(FileKind::Directory, bytes.as_slice())?;
+
+        // Version 1 only added the file header: the body is unchanged
+        if version != LEGACY_VERSION && version != VERSION {
+            return PersistenceError::UnsupportedVersion { version }
+                .fail()
+                .spot(here!());
+        }
 
-        let chunks = bincode::deserialize::<Vec<Vec<u8>>>(bytes.as_slice()).unwrap();
+        let chunks = persistence::deserialize_body::<Vec<Vec<u8>>>(version, body)?;
 
-        let keycards = chunks
+        let chunks = chunks
             .par_iter()
-            .map(|chunk| bincode::deserialize::<Vec<Option<KeyCard>>>(chunk).unwrap())
-            .flatten()
-            .collect::<Vec<_>>();
+            .map(|chunk| persistence::deserialize::<Vec<Option<KeyCard>>>(chunk))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        Directory { keycards }
+        let keycards = chunks.into_iter().flatten().collect::<Vec<_>>();
+
+        Ok(Directory::from_keycards(keycards))
     }
 
-    pub fn keycard(&self, id: u64) -> Option<&KeyCard> {
-        self.keycards.get(id as usize).map(Option::as_ref).flatten()
+    /// Memory-maps a file written by `save_indexed`. `KeyCard`s are decoded
+    /// on demand, and the most recently used ones are kept in memory.
+    pub fn map<P>(path: P) -> Result<Directory, Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        Directory::map_with_cache(path, CACHE)
+    }
+
+    /// Like `map`, keeping up to `cache` decoded `KeyCard`s in memory.
+    pub fn map_with_cache<P>(path: P, cache: usize) -> Result<Directory, Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let keycards = MappedKeycards::open(path, cache)?;
+
+        Ok(Directory {
+            keycards: KeyCards::Mapped(Arc::new(keycards)),
+        })
+    }
+
+    /// Panics if `self` is mapped and the entry of `id` is corrupted: entries
+    /// that might come from untrusted input should go through `try_keycard`.
+    pub fn keycard(&self, id: u64) -> Option<Arc<KeyCard>> {
+        self.try_keycard(id).unwrap()
+    }
+
+    /// Like `keycard`, but reports corrupted entries of a mapped `Directory`
+    /// (which are only decoded, and checked, on access).
+    pub fn try_keycard(&self, id: u64) -> Result<Option<Arc<KeyCard>>, Top<PersistenceError>> {
+        match &self.keycards {
+            KeyCards::Loaded(keycards) => Ok(keycards.get(id as usize).cloned().flatten()),
+            KeyCards::Mapped(keycards) => keycards.keycard(id),
+        }
     }
 
     pub fn capacity(&self) -> usize {
-        self.keycards.len()
+        match &self.keycards {
+            KeyCards::Loaded(keycards) => keycards.len(),
+            KeyCards::Mapped(keycards) => keycards.capacity(),
+        }
     }
 
-    pub fn save(&self, path: &str) {
-        let chunk_size = (self.keycards.len() + CHUNKS - 1) / CHUNKS;
+    pub fn save<P>(&self, path: P) -> Result<(), Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let keycards = self.collect()?;
+        let chunk_size = (keycards.len() + CHUNKS - 1) / CHUNKS;
+
+        let chunks = keycards
+            .chunks(chunk_size.max(1))
+            .map(|chunk| persistence::serialize(&chunk))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let body = persistence::serialize(&chunks)?;
+        persistence::write(path, FileKind::Directory, VERSION, body.as_slice())
+    }
+
+    /// Saves `self` in the indexed format expected by `map`.
+    pub fn save_indexed<P>(&self, path: P) -> Result<(), Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let keycards = self.collect()?;
+        let body = MappedKeycards::body(keycards);
+
+        persistence::write(
+            path,
+            FileKind::IndexedDirectory,
+            mapped_keycards::VERSION,
+            body.as_slice(),
+        )
+    }
+
+    fn collect(&self) -> Result<Vec<Option<Arc<KeyCard>>>, Top<PersistenceError>> {
+        match &self.keycards {
+            KeyCards::Loaded(keycards) => Ok(keycards.clone()),
+            KeyCards::Mapped(keycards) => (0..keycards.capacity() as u64)
+                .map(|id| keycards.keycard(id))
+                .collect(),
+        }
+    }
+}
+
+impl Serialize for Directory {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Directory", 1)?;
+
+        match &self.keycards {
+            KeyCards::Loaded(keycards) => state.serialize_field("keycards", keycards)?,
+            KeyCards::Mapped(_) => {
+                let keycards = self
+                    .collect()
+                    .map_err(|error| S::Error::custom(format!("{:?}", error)))?;
+
+                state.serialize_field("keycards", &keycards)?
+            }
+        }
+
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Directory {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let serialized = Serialized::deserialize(deserializer)?;
+        Ok(Directory::from_keycards(serialized.keycards))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::passepartout::Passepartout;
+
+    #[test]
+    fn map() {
+        let passepartout = Passepartout::random(100);
+        let (_membership, original) = passepartout.system(4);
+
+        original.save_indexed("assets/directory.idx").unwrap();
+        // A cache smaller than the directory forces evictions
+        let mapped = Directory::map_with_cache("assets/directory.idx", 10).unwrap();
 
-        let chunks = self
-            .keycards
-            .chunks(chunk_size)
-            .map(|chunk| bincode::serialize(&chunk).unwrap())
-            .collect::<Vec<_>>();
+        assert_eq!(mapped.capacity(), original.capacity());
 
-        fs::write(path, bincode::serialize(&chunks).unwrap().as_slice()).unwrap();
+        for id in 0..(original.capacity() as u64 + 1) {
+            assert_eq!(
+                mapped.keycard(id).map(|keycard| keycard.identity()),
+                original.keycard(id).map(|keycard| keycard.identity())
+            );
+        }
     }
 }
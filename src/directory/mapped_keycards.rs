@@ -0,0 +1,174 @@
+use crate::persistence::{self, FileKind, PersistenceError};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use lru::LruCache;
+
+use memmap2::Mmap;
+
+use std::{
+    convert::TryInto,
+    fs::File,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use talk::crypto::KeyCard;
+
+// Indexed directory body layout (all integers little-endian, offsets relative
+// to the start of the body):
+//
+//   [0..8)                      capacity (u64)
+//   [8..8 + 8 * (capacity + 1)) offsets (u64), relative to the start of the data section
+//   [8 + 8 * (capacity + 1)..)  data: `bincode`-serialized `KeyCard`s, back to back
+//
+// The `KeyCard` of `id` is stored in `data[offsets[id]..offsets[id + 1]]`. An
+// empty range denotes an empty slot.
+pub(in crate::directory) const VERSION: u16 = 1;
+const OFFSET_SIZE: usize = 8;
+const INDEX_START: usize = 8;
+
+pub(in crate::directory) struct MappedKeycards {
+    mmap: Mmap,
+    body_start: usize,
+    capacity: usize,
+    data_start: usize,
+    // Most recently used `KeyCard`s: evicted ones remain valid for as long as
+    // their callers hold them
+    cache: Mutex<LruCache<u64, Arc<KeyCard>>>,
+}
+
+impl MappedKeycards {
+    /// Maps the file at `path`. Header, kind, version and length are checked
+    /// eagerly, but the checksum is not: hashing the whole file would defeat
+    /// the purpose of decoding `KeyCard`s on demand.
+    pub fn open<P>(path: P, cache: usize) -> Result<Self, Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(path)
+            .map_err(PersistenceError::read_failed)
+            .map_err(PersistenceError::into_top)
+            .spot(here!())?;
+
+        // Safety: the file is only ever read, and key files are not expected
+        // to be modified while a `Directory` maps them.
+        let mmap = unsafe { Mmap::map(&file) }
+            .map_err(PersistenceError::read_failed)
+            .map_err(PersistenceError::into_top)
+            .spot(here!())?;
+
+        let (version, body) = persistence::open_unchecked(FileKind::IndexedDirectory, &mmap)?;
+
+        if version != VERSION {
+            return PersistenceError::UnsupportedVersion { version }
+                .fail()
+                .spot(here!());
+        }
+
+        let body_start = body.start;
+        let body = &mmap[body];
+
+        if body.len() < INDEX_START {
+            return PersistenceError::Truncated.fail().spot(here!());
+        }
+
+        let capacity = u64::from_le_bytes(body[0..8].try_into().unwrap()) as usize;
+
+        let data_start = capacity
+            .checked_add(1)
+            .and_then(|offsets| offsets.checked_mul(OFFSET_SIZE))
+            .and_then(|index| index.checked_add(INDEX_START))
+            .filter(|data_start| *data_start <= body.len())
+            .ok_or_else(|| PersistenceError::Truncated.into_top())
+            .spot(here!())?;
+
+        let cache = Mutex::new(LruCache::new(cache));
+
+        Ok(MappedKeycards {
+            mmap,
+            body_start,
+            capacity,
+            data_start,
+            cache,
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// `None` if the slot of `id` is empty, an error if its entry is corrupted.
+    pub fn keycard(&self, id: u64) -> Result<Option<Arc<KeyCard>>, Top<PersistenceError>> {
+        if id >= self.capacity as u64 {
+            return Ok(None);
+        }
+
+        if let Some(keycard) = self.cache.lock().unwrap().get(&id) {
+            return Ok(Some(keycard.clone()));
+        }
+
+        let index = id as usize;
+
+        let start = self.offset(index);
+        let end = self.offset(index + 1);
+
+        if start == end {
+            return Ok(None);
+        }
+
+        // Decode outside of the lock: concurrent misses on the same `id` only
+        // waste work
+        let keycard = Arc::new(self.decode(start, end)?);
+        self.cache.lock().unwrap().put(id, keycard.clone());
+
+        Ok(Some(keycard))
+    }
+
+    fn decode(&self, start: usize, end: usize) -> Result<KeyCard, Top<PersistenceError>> {
+        let data = &self.mmap[self.body_start + self.data_start..];
+
+        let bytes = data
+            .get(start..end)
+            .ok_or_else(|| PersistenceError::Truncated.into_top())
+            .spot(here!())?;
+
+        persistence::deserialize(bytes)
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        let start = self.body_start + INDEX_START + index * OFFSET_SIZE;
+        let bytes = self.mmap[start..start + OFFSET_SIZE].try_into().unwrap();
+        u64::from_le_bytes(bytes) as usize
+    }
+
+    pub fn body<K>(keycards: K) -> Vec<u8>
+    where
+        K: IntoIterator<Item = Option<Arc<KeyCard>>>,
+    {
+        let mut offsets = Vec::new();
+        let mut data = Vec::new();
+
+        offsets.push(0u64);
+
+        for keycard in keycards {
+            if let Some(keycard) = keycard {
+                bincode::serialize_into(&mut data, keycard.as_ref()).unwrap();
+            }
+
+            offsets.push(data.len() as u64);
+        }
+
+        let capacity = offsets.len() - 1;
+        let mut bytes = Vec::with_capacity(INDEX_START + offsets.len() * OFFSET_SIZE + data.len());
+
+        bytes.extend_from_slice(&(capacity as u64).to_le_bytes());
+
+        for offset in offsets {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(data.as_slice());
+        bytes
+    }
+}
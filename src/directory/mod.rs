@@ -1,3 +1,4 @@
 mod directory;
+mod mapped_keycards;
 
 pub use directory::Directory;
@@ -0,0 +1,246 @@
+use crate::{dispersal::Fragment, membership::Membership};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use std::{convert::TryInto, mem};
+
+use talk::crypto::primitives::hash::Hash;
+
+use zebra::vector::Vector;
+
+const LENGTH_SIZE: usize = mem::size_of::<u64>();
+
+/// Erasure-codes (compressed) batches into one `Fragment` per server, any
+/// `data` of which suffice to reconstruct the batch.
+///
+/// Before coding, bytes are prefixed with their length and zero-padded to a
+/// multiple of `data`. The dispersal commitment is the root of a Merkle tree
+/// whose leaves are the `(index, chunk)` pairs of all fragments.
+#[derive(Debug, Clone, Copy)]
+pub struct Dispersal {
+    data: usize,
+    total: usize,
+}
+
+#[derive(Doom)]
+pub enum DispersalError {
+    #[doom(description("Fragment invalid"))]
+    FragmentInvalid,
+    #[doom(description("Not enough fragments ({} out of {})", available, required))]
+    NotEnoughFragments { available: usize, required: usize },
+    #[doom(description("Failed to reconstruct data"))]
+    ReconstructFailed,
+    #[doom(description(
+        "Inconsistent encoding (reconstructed data does not match the commitment)"
+    ))]
+    InconsistentEncoding,
+}
+
+impl Dispersal {
+    /// A dispersal across all servers in `membership`. A quorum of servers
+    /// witnesses having stored its fragment: correct servers among them hold
+    /// at least a plurality of the weight. Any set of servers with that much
+    /// weight has at least as many servers as the heaviest such set, whose
+    /// size is the number of fragments needed to reconstruct.
+    pub fn new(membership: &Membership) -> Self {
+        let mut weights = membership
+            .servers()
+            .keys()
+            .map(|identity| membership.weight(*identity))
+            .collect::<Vec<_>>();
+
+        weights.sort_unstable_by(|left, right| right.cmp(left));
+
+        let data = weights
+            .iter()
+            .scan(0, |power, weight| {
+                *power += weight;
+                Some(*power)
+            })
+            .position(|power| power >= membership.plurality())
+            .unwrap()
+            + 1;
+
+        Dispersal::with_parameters(data, membership.servers().len())
+    }
+
+    pub fn with_parameters(data: usize, total: usize) -> Self {
+        assert!(
+            data > 0 && data <= total,
+            "Called `Dispersal::with_parameters` with inconsistent parameters"
+        );
+
+        Dispersal { data, total }
+    }
+
+    /// Number of fragments needed to reconstruct.
+    pub fn data(&self) -> usize {
+        self.data
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Returns the dispersal commitment of `bytes`, along with one fragment
+    /// per server (the `i`-th fragment is meant for the `i`-th server).
+    pub fn encode(&self, bytes: &[u8]) -> (Hash, Vec<Fragment>) {
+        let leaves = self
+            .chunks(bytes)
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| (index as u32, chunk))
+            .collect::<Vec<_>>();
+
+        let vector = Vector::new(leaves).unwrap();
+
+        let fragments = vector
+            .items()
+            .iter()
+            .enumerate()
+            .map(|(index, leaf)| Fragment::new(leaf.clone(), vector.prove(index)))
+            .collect();
+
+        (vector.root(), fragments)
+    }
+
+    /// Reconstructs the bytes committed to by `commitment` from (at least
+    /// `self.data()`) `fragments`. Because every fragment is checked against
+    /// `commitment`, and reconstructed bytes are re-encoded and checked
+    /// against `commitment`, every set of fragments yields the same outcome.
+    pub fn decode<F>(&self, commitment: Hash, fragments: F) -> Result<Vec<u8>, Top<DispersalError>>
+    where
+        F: IntoIterator<Item = Fragment>,
+    {
+        let mut chunks = vec![None; self.total];
+        let mut available = 0;
+
+        for fragment in fragments {
+            fragment.verify(commitment, self.total)?;
+            let index = fragment.index();
+
+            if chunks[index].is_none() {
+                chunks[index] = Some(fragment.into_chunk());
+                available += 1;
+            }
+        }
+
+        if available < self.data {
+            return DispersalError::NotEnoughFragments {
+                available,
+                required: self.data,
+            }
+            .fail()
+            .spot(here!());
+        }
+
+        if let Some(coder) = self.coder() {
+            coder
+                .reconstruct_data(chunks.as_mut_slice())
+                .map_err(|_| DispersalError::ReconstructFailed.into_top())
+                .spot(here!())?;
+        }
+
+        let data = chunks
+            .into_iter()
+            .take(self.data)
+            .flat_map(Option::unwrap)
+            .collect::<Vec<_>>();
+
+        let bytes = data
+            .get(0..LENGTH_SIZE)
+            .map(|length| u64::from_le_bytes(length.try_into().unwrap()) as usize)
+            .and_then(|length| data.get(LENGTH_SIZE..LENGTH_SIZE.checked_add(length)?))
+            .ok_or_else(|| DispersalError::InconsistentEncoding.into_top())
+            .spot(here!())?;
+
+        if self.encode(bytes).0 != commitment {
+            return DispersalError::InconsistentEncoding.fail().spot(here!());
+        }
+
+        Ok(bytes.to_vec())
+    }
+
+    fn chunks(&self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut data = Vec::with_capacity(LENGTH_SIZE + bytes.len() + self.data);
+        data.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        data.extend_from_slice(bytes);
+
+        let size = (data.len() + self.data - 1) / self.data;
+        data.resize(size * self.data, 0);
+
+        let mut chunks = data
+            .chunks(size)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+
+        chunks.resize(self.total, vec![0; size]);
+
+        if let Some(coder) = self.coder() {
+            coder.encode(chunks.as_mut_slice()).unwrap();
+        }
+
+        chunks
+    }
+
+    // `None` if there is no parity to compute (`data == total`)
+    fn coder(&self) -> Option<ReedSolomon> {
+        if self.data < self.total {
+            Some(ReedSolomon::new(self.data, self.total - self.data).unwrap())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::prelude::*;
+
+    #[test]
+    fn reconstruct() {
+        let mut rng = thread_rng();
+
+        for (data, total) in [(1, 1), (1, 4), (2, 4), (3, 7), (5, 16)] {
+            let dispersal = Dispersal::with_parameters(data, total);
+
+            let mut bytes = vec![0u8; rng.gen_range(0..4096)];
+            rng.fill_bytes(bytes.as_mut_slice());
+
+            let (commitment, mut fragments) = dispersal.encode(bytes.as_slice());
+            assert_eq!(fragments.len(), total);
+
+            fragments.shuffle(&mut rng);
+
+            let decoded = dispersal
+                .decode(commitment, fragments.iter().take(data).cloned())
+                .unwrap();
+
+            assert_eq!(decoded, bytes);
+
+            if data > 1 {
+                let error = dispersal
+                    .decode(commitment, fragments.iter().take(data - 1).cloned())
+                    .err()
+                    .unwrap();
+
+                assert!(matches!(
+                    error.top(),
+                    DispersalError::NotEnoughFragments { .. }
+                ));
+            }
+
+            let (other, _) = dispersal.encode(b"other");
+            let error = dispersal
+                .decode(other, fragments.iter().take(data).cloned())
+                .err()
+                .unwrap();
+
+            assert!(matches!(error.top(), DispersalError::FragmentInvalid));
+        }
+    }
+}
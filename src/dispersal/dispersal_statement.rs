@@ -0,0 +1,24 @@
+use crate::crypto::{Domain, Header};
+
+use serde::Serialize;
+
+use talk::crypto::{primitives::hash::Hash, Statement};
+
+/// Multi-signed by a server that stored its fragment of the dispersed batch
+/// with a given commitment. A quorum of witnesses makes it orderable.
+#[derive(Debug, Clone, Serialize)]
+pub struct DispersalStatement {
+    domain: Domain,
+    commitment: Hash,
+}
+
+impl DispersalStatement {
+    pub fn new(domain: Domain, commitment: Hash) -> Self {
+        DispersalStatement { domain, commitment }
+    }
+}
+
+impl Statement for DispersalStatement {
+    type Header = Header;
+    const HEADER: Header = Header::Dispersal;
+}
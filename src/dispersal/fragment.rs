@@ -0,0 +1,48 @@
+use crate::dispersal::DispersalError;
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::primitives::hash::Hash;
+
+use zebra::vector::Proof;
+
+/// One erasure-coded chunk of a batch, along with the Merkle proof that it
+/// is the `index`-th leaf of the dispersal commitment.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fragment {
+    leaf: (u32, Vec<u8>), // The index is part of the leaf, so that it is committed to
+    proof: Proof,
+}
+
+impl Fragment {
+    pub(in crate::dispersal) fn new(leaf: (u32, Vec<u8>), proof: Proof) -> Self {
+        Fragment { leaf, proof }
+    }
+
+    /// Index of the server this fragment is meant for (in `Membership` order).
+    pub fn index(&self) -> usize {
+        self.leaf.0 as usize
+    }
+
+    pub fn chunk(&self) -> &[u8] {
+        self.leaf.1.as_slice()
+    }
+
+    pub(in crate::dispersal) fn into_chunk(self) -> Vec<u8> {
+        self.leaf.1
+    }
+
+    /// Checks that `self` is part of the commitment of a batch dispersed
+    /// across `total` servers.
+    pub fn verify(&self, commitment: Hash, total: usize) -> Result<(), Top<DispersalError>> {
+        if self.index() >= total {
+            return DispersalError::FragmentInvalid.fail().spot(here!());
+        }
+
+        self.proof
+            .verify(commitment, &self.leaf)
+            .pot(DispersalError::FragmentInvalid, here!())
+    }
+}
@@ -0,0 +1,7 @@
+mod dispersal;
+mod dispersal_statement;
+mod fragment;
+
+pub use dispersal::{Dispersal, DispersalError};
+pub use dispersal_statement::DispersalStatement;
+pub use fragment::Fragment;
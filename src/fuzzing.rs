@@ -0,0 +1,53 @@
+//! Entry points for the `cargo fuzz` targets in `fuzz/`. Every function
+//! accepts arbitrary bytes and must never panic. Not part of the stable API.
+
+use crate::{
+    batch::{CompressedBatch, CompressedBatchView},
+    crypto::Domain,
+    directory::Directory,
+    membership::{Certificate, CertificateVerifier, Membership},
+    server::{Server, WitnessStatement},
+};
+
+use std::collections::HashSet;
+
+use talk::crypto::primitives::hash;
+
+/// Decodes, decompresses and verifies a batch, both through `CompressedBatch`
+/// and through `CompressedBatchView` (as `Server::serve` does).
+pub fn decode_batch(directory: &Directory, bytes: &[u8]) {
+    if let Ok(batch) = bincode::deserialize::<CompressedBatch>(bytes) {
+        if let Ok(batch) = batch.decompress() {
+            let _ = batch.verify(Domain::default(), directory);
+            let _ = batch.par_verify(Domain::default(), directory);
+        }
+    }
+
+    if let Ok(view) = CompressedBatchView::new(bytes) {
+        let _ = view.payloads().count();
+        let _ = view.into_batch().verify(Domain::default(), directory);
+    }
+}
+
+/// Decodes a `Certificate` and verifies it as a witness for a fixed root.
+pub fn verify_certificate(membership: &Membership, bytes: &[u8]) {
+    if let Ok(certificate) = bincode::deserialize::<Certificate>(bytes) {
+        let root = hash::hash(&0u64).unwrap();
+        let _ = certificate.power(membership);
+        let _ = certificate.verify_plurality(
+            membership,
+            &WitnessStatement::new(membership.domain(), root),
+        );
+        let _ = certificate.verify_quorum(
+            membership,
+            &WitnessStatement::new(membership.domain(), root),
+        );
+    }
+}
+
+/// Parses a submission delivered by the ordering layer, alone and in a
+/// burst with itself, as `Server::deliver` does.
+pub fn process_submission(verifier: &CertificateVerifier, bytes: &[u8]) {
+    let _ = Server::parse_submissions(verifier, &HashSet::new(), [bytes]);
+    let _ = Server::parse_submissions(verifier, &HashSet::new(), [bytes, bytes]);
+}
@@ -0,0 +1,363 @@
+use crate::{
+    keystore::Secret,
+    persistence::{self, FileKind},
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use rand::prelude::*;
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use serde::{Deserialize, Serialize};
+
+use std::{collections::HashMap, io, ops::Range, path::Path};
+
+use talk::crypto::{Identity, KeyChain};
+
+use zeroize::Zeroizing;
+
+// Keystore body layout: a `bincode`-serialized `Index`, followed by the
+// encrypted entries back to back. Every entry is a `NONCE_SIZE`-byte nonce
+// followed by the ChaCha20-Poly1305 encryption of a `bincode`-serialized
+// `KeyChain`, authenticated together with the `Identity` it belongs to.
+//
+// All entries are encrypted under the same key, derived once from the
+// `Secret` using Argon2id with the parameters stored in the `Index`. This
+// allows a single `KeyChain` to be decrypted without touching the others.
+//
+// The Argon2id parameters are authenticated along with the canary. Version 1,
+// which left them unauthenticated, is no longer accepted.
+const VERSION: u16 = 2;
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const CANARY: &[u8] = b"pod keystore";
+
+// Argon2id parameters are read before they can be authenticated: they are
+// bounded, so that a tampered keystore cannot make the derivation arbitrarily
+// expensive
+const MAX_MEMORY: u32 = 1024 * 1024; // KiB
+const MAX_ITERATIONS: u32 = 64;
+const MAX_PARALLELISM: u32 = 64;
+
+/// Argon2id parameters, stored in the clear (but authenticated) in every
+/// keystore. Parameters above 1 GiB of memory, 64 iterations or 64 lanes are
+/// rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParameters {
+    pub memory: u32, // KiB
+    pub iterations: u32,
+    pub parallelism: u32,
+    pub salt: [u8; 16],
+}
+
+#[derive(Serialize, Deserialize)]
+struct Index {
+    kdf: KdfParameters,
+    canary: Vec<u8>, // Encryption of `CANARY`, used to detect a wrong `Secret`
+    entries: Vec<(Identity, u64, u64)>, // In the order `KeyChain`s were provided
+}
+
+/// An opened keystore. Entries are only decrypted on request.
+pub struct Keystore {
+    key: Zeroizing<[u8; KEY_SIZE]>,
+    entries: Vec<(Identity, u64, u64)>,
+    positions: HashMap<Identity, usize>,
+    bytes: Vec<u8>,
+    data: Range<usize>, // Encrypted entries, within `bytes`
+}
+
+#[derive(Doom)]
+pub enum KeystoreError {
+    #[doom(description("Failed to read key file: {:?}", source))]
+    #[doom(wrap(key_file_unreadable))]
+    KeyFileUnreadable { source: io::Error },
+    #[doom(description("Failed to load keystore"))]
+    LoadFailed,
+    #[doom(description("Failed to save keystore"))]
+    SaveFailed,
+    #[doom(description("Invalid key derivation parameters"))]
+    KdfInvalid,
+    #[doom(description("Wrong secret"))]
+    WrongSecret,
+    #[doom(description("Keystore malformed"))]
+    KeystoreMalformed,
+    #[doom(description("Unknown identity"))]
+    UnknownIdentity,
+}
+
+impl KdfParameters {
+    pub fn random() -> Self {
+        KdfParameters {
+            memory: 64 * 1024,
+            iterations: 3,
+            parallelism: 4,
+            salt: random(),
+        }
+    }
+
+    fn derive(&self, secret: &Secret) -> Result<Zeroizing<[u8; KEY_SIZE]>, Top<KeystoreError>> {
+        if self.memory > MAX_MEMORY
+            || self.iterations > MAX_ITERATIONS
+            || self.parallelism > MAX_PARALLELISM
+        {
+            return KeystoreError::KdfInvalid.fail().spot(here!());
+        }
+
+        let params = Params::new(
+            self.memory,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_SIZE),
+        )
+        .map_err(|_| KeystoreError::KdfInvalid.into_top())
+        .spot(here!())?;
+
+        let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(secret.bytes()?.as_slice(), &self.salt, &mut *key)
+            .map_err(|_| KeystoreError::KdfInvalid.into_top())
+            .spot(here!())?;
+
+        Ok(key)
+    }
+}
+
+impl Keystore {
+    pub fn save<'k, P, K>(
+        path: P,
+        keychains: K,
+        secret: &Secret,
+        kdf: KdfParameters,
+    ) -> Result<(), Top<KeystoreError>>
+    where
+        P: AsRef<Path>,
+        K: IntoIterator<Item = &'k KeyChain>,
+    {
+        let key = kdf.derive(secret)?;
+        let canary = Keystore::encrypt(&key, CANARY, bincode::serialize(&kdf).unwrap().as_slice());
+
+        let keychains = keychains
+            .into_iter()
+            .map(|keychain| (keychain.keycard().identity(), keychain))
+            .collect::<Vec<_>>();
+
+        let sealed = keychains
+            .par_iter()
+            .map(|(identity, keychain)| {
+                let keychain = bincode::serialize(keychain).unwrap();
+                let identity = bincode::serialize(identity).unwrap();
+                Keystore::encrypt(&key, keychain.as_slice(), identity.as_slice())
+            })
+            .collect::<Vec<_>>();
+
+        let mut entries = Vec::with_capacity(sealed.len());
+        let mut data = Vec::with_capacity(sealed.iter().map(Vec::len).sum());
+
+        for ((identity, _), entry) in keychains.iter().zip(sealed) {
+            entries.push((*identity, data.len() as u64, entry.len() as u64));
+            data.extend_from_slice(entry.as_slice());
+        }
+
+        let index = Index {
+            kdf,
+            canary,
+            entries,
+        };
+
+        let mut body = bincode::serialize(&index).unwrap();
+        body.extend_from_slice(data.as_slice());
+
+        persistence::write(path, FileKind::Keystore, VERSION, body.as_slice())
+            .pot(KeystoreError::SaveFailed, here!())
+    }
+
+    pub fn open<P>(path: P, secret: &Secret) -> Result<Self, Top<KeystoreError>>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = persistence::read(path).pot(KeystoreError::LoadFailed, here!())?;
+
+        let (version, body) = persistence::open(FileKind::Keystore, bytes.as_slice())
+            .pot(KeystoreError::LoadFailed, here!())?;
+
+        if version != VERSION {
+            return KeystoreError::KeystoreMalformed.fail().spot(here!());
+        }
+
+        let mut reader = body;
+
+        let index = bincode::deserialize_from::<_, Index>(&mut reader)
+            .map_err(|_| KeystoreError::KeystoreMalformed.into_top())
+            .spot(here!())?;
+
+        let data = (bytes.len() - reader.len())..bytes.len();
+
+        let key = index.kdf.derive(secret)?;
+
+        let aad = bincode::serialize(&index.kdf).unwrap();
+
+        match Keystore::decrypt(&key, index.canary.as_slice(), aad.as_slice()) {
+            Some(canary) if canary == CANARY => {}
+            _ => return KeystoreError::WrongSecret.fail().spot(here!()),
+        }
+
+        let positions = index
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(position, (identity, _, _))| (*identity, position))
+            .collect::<HashMap<_, _>>();
+
+        Ok(Keystore {
+            key,
+            entries: index.entries,
+            positions,
+            bytes,
+            data,
+        })
+    }
+
+    pub fn identities(&self) -> impl Iterator<Item = Identity> + '_ {
+        self.entries.iter().map(|(identity, _, _)| *identity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Decrypts the `KeyChain` of `identity` alone.
+    pub fn keychain(&self, identity: Identity) -> Result<KeyChain, Top<KeystoreError>> {
+        let position = self
+            .positions
+            .get(&identity)
+            .ok_or_else(|| KeystoreError::UnknownIdentity.into_top())
+            .spot(here!())?;
+
+        self.decrypt_entry(&self.entries[*position])
+    }
+
+    pub fn keychains(&self) -> Result<Vec<KeyChain>, Top<KeystoreError>> {
+        self.entries
+            .par_iter()
+            .map(|entry| self.decrypt_entry(entry))
+            .collect()
+    }
+
+    fn decrypt_entry(
+        &self,
+        (identity, offset, length): &(Identity, u64, u64),
+    ) -> Result<KeyChain, Top<KeystoreError>> {
+        let entry = (*offset as usize)
+            .checked_add(*length as usize)
+            .and_then(|end| self.bytes[self.data.clone()].get((*offset as usize)..end))
+            .ok_or_else(|| KeystoreError::KeystoreMalformed.into_top())
+            .spot(here!())?;
+
+        let identity = bincode::serialize(identity).unwrap();
+
+        let keychain = Keystore::decrypt(&self.key, entry, identity.as_slice())
+            .ok_or_else(|| KeystoreError::KeystoreMalformed.into_top())
+            .spot(here!())?;
+
+        bincode::deserialize(keychain.as_slice())
+            .map_err(|_| KeystoreError::KeystoreMalformed.into_top())
+            .spot(here!())
+    }
+
+    fn encrypt(key: &[u8; KEY_SIZE], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+        let nonce: [u8; NONCE_SIZE] = random();
+
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .unwrap();
+
+        let mut entry = nonce.to_vec();
+        entry.extend_from_slice(ciphertext.as_slice());
+        entry
+    }
+
+    fn decrypt(key: &[u8; KEY_SIZE], entry: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if entry.len() < NONCE_SIZE {
+            return None;
+        }
+
+        let (nonce, ciphertext) = entry.split_at(NONCE_SIZE);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+
+        cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cheap_kdf() -> KdfParameters {
+        KdfParameters {
+            memory: 8,
+            iterations: 1,
+            parallelism: 1,
+            salt: random(),
+        }
+    }
+
+    #[test]
+    fn extract() {
+        let keychains = (0..10).map(|_| KeyChain::random()).collect::<Vec<_>>();
+        let secret = Secret::Password("hunter2".to_string());
+
+        Keystore::save(
+            "assets/keystore.bin",
+            keychains.iter(),
+            &secret,
+            cheap_kdf(),
+        )
+        .unwrap();
+        let keystore = Keystore::open("assets/keystore.bin", &secret).unwrap();
+
+        assert_eq!(keystore.len(), 10);
+
+        for keychain in keychains.iter() {
+            let identity = keychain.keycard().identity();
+            let extracted = keystore.keychain(identity).unwrap();
+            assert_eq!(extracted.keycard().identity(), identity);
+        }
+
+        let wrong = Secret::Password("hunter3".to_string());
+        assert!(Keystore::open("assets/keystore.bin", &wrong).is_err());
+
+        let expensive = KdfParameters {
+            memory: u32::MAX,
+            ..cheap_kdf()
+        };
+
+        let error = Keystore::save("assets/keystore.bin", keychains.iter(), &secret, expensive)
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), KeystoreError::KdfInvalid));
+    }
+}
@@ -0,0 +1,5 @@
+mod keystore;
+mod secret;
+
+pub use keystore::{KdfParameters, Keystore, KeystoreError};
+pub use secret::Secret;
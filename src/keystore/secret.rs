@@ -0,0 +1,27 @@
+use crate::keystore::KeystoreError;
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use std::{fs, path::PathBuf};
+
+use zeroize::Zeroizing;
+
+/// The secret protecting a keystore. A key file is used exactly like a
+/// password: its whole content goes through the key derivation function.
+pub enum Secret {
+    Password(String),
+    KeyFile(PathBuf),
+}
+
+impl Secret {
+    pub(in crate::keystore) fn bytes(&self) -> Result<Zeroizing<Vec<u8>>, Top<KeystoreError>> {
+        match self {
+            Secret::Password(password) => Ok(Zeroizing::new(password.as_bytes().to_vec())),
+            Secret::KeyFile(path) => fs::read(path)
+                .map(Zeroizing::new)
+                .map_err(KeystoreError::key_file_unreadable)
+                .map_err(KeystoreError::into_top)
+                .spot(here!()),
+        }
+    }
+}
@@ -3,14 +3,34 @@ mod broadcast;
 mod brokers;
 mod crypto;
 mod directory;
+mod dispersal;
+mod keystore;
 mod membership;
 mod passepartout;
+mod persistence;
 mod server;
 
-pub use batch::{Batch, BatchError, CompressedBatch, Message, Payload};
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+
+pub use batch::{
+    Batch, BatchBuilder, BatchError, BatchReport, BroadcastStatement, CompressedBatch,
+    CompressedBatchView, Compression, InclusionProof, Message, Payload, ReductionStatement,
+    SealedBatch,
+};
 pub use broadcast::{BftSmart, Broadcast, HotStuff, LoopBack};
-pub use brokers::LoadBroker;
+pub use brokers::{LoadBroker, LoadBrokerError};
+pub use crypto::{Domain, Header};
 pub use directory::Directory;
-pub use membership::{Certificate, CertificateError, Membership};
+pub use dispersal::{Dispersal, DispersalError, DispersalStatement, Fragment};
+pub use keystore::{KdfParameters, Keystore, KeystoreError, Secret};
+pub use membership::{
+    Certificate, CertificateError, CertificateVerifier, FaultModel, Membership, MembershipError,
+    Threshold, ThresholdKeyChain, ThresholdKeys, ThresholdKeysError, ThresholdShard,
+};
 pub use passepartout::Passepartout;
-pub use server::Server;
+pub use persistence::PersistenceError;
+pub use server::{
+    Equivocation, EquivocationDetector, EquivocationError, OrderStatement, Position, Receipt,
+    ReceiptError, ReceiptStatement, Server, WitnessStatement,
+};
@@ -1,17 +1,42 @@
 use bit_vec::BitVec;
 
-use crate::membership::Membership;
+use blsttc::Signature as ThresholdSignature;
+
+use crate::membership::{threshold, Membership, Threshold, ThresholdShard};
 
 use doomstack::{here, Doom, ResultExt, Top};
 
 use serde::{Deserialize, Serialize};
 
-use talk::crypto::{primitives::multi::Signature as MultiSignature, Identity, Statement};
+use std::{
+    collections::{BTreeMap, HashSet},
+    iter,
+};
+
+use talk::crypto::{
+    primitives::multi::{PublicKey as MultiPublicKey, Signature as MultiSignature},
+    Identity, Statement,
+};
 
+/// Proves that servers with enough weight signed a statement. Certificates
+/// are either aggregated multi-signatures, carrying one bit per server, or
+/// constant-size threshold signatures (see `ThresholdKeys`), verified through
+/// the same `verify_*` methods.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Certificate {
-    signers: BitVec,
-    signature: MultiSignature,
+    backend: Backend,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum Backend {
+    Multi {
+        signers: BitVec,
+        signature: MultiSignature,
+    },
+    Threshold {
+        threshold: Threshold,
+        signature: ThresholdSignature,
+    },
 }
 
 #[derive(Doom)]
@@ -22,6 +47,16 @@ pub enum CertificateError {
     NotEnoughSigners,
     #[doom(description("Overlapping signers"))]
     OverlappingSigners,
+    #[doom(description("Signer is not a server: {:?}", identity))]
+    ForeignSigner { identity: Identity },
+    #[doom(description("Failed to aggregate signatures"))]
+    AggregationFailed,
+    #[doom(description("Membership has no threshold keys"))]
+    ThresholdKeysMissing,
+    #[doom(description("Operation not supported by the certificate's backend"))]
+    BackendMismatch,
+    #[doom(description("Threshold does not match the one requested"))]
+    ThresholdMismatch,
 }
 
 impl Certificate {
@@ -29,33 +64,9 @@ impl Certificate {
     where
         C: IntoIterator<Item = (Identity, MultiSignature)>,
     {
-        let mut components = components.into_iter().collect::<Vec<_>>();
-        components.sort_by_key(|component| component.0);
-
-        let mut signers = BitVec::from_elem(membership.servers().len(), false);
-        let mut signer_identities = components.iter().map(|component| component.0).peekable();
-
-        // Both `membership.members()` and `signer_identities` are sorted. In order to determine which
-        // elements of `signers` to set to `true`, loop through all elements of `membership.members()`:
-        // for every `member`, if `member` is the next element of `signer_identities`, then set the
-        // corresponding element of `signers` to `true`, and move `signer_identities` on.
-        for (index, member) in membership.servers().keys().enumerate() {
-            if signer_identities.peek() == Some(&member) {
-                signers.set(index, true);
-                signer_identities.next().unwrap();
-            }
-        }
-
-        if signer_identities.next().is_some() {
-            panic!("Called `Certificate::aggregate` with a foreign component");
-        }
-
-        let signatures = components.into_iter().map(|component| component.1);
-
-        let signature = MultiSignature::aggregate(signatures)
-            .expect("Called `Certificate::aggregate` with an incorrect multi-signature");
-
-        Certificate { signers, signature }
+        Certificate::try_aggregate(membership, components).expect(
+            "Called `Certificate::aggregate` with a foreign, duplicate or incorrect component",
+        )
     }
 
     pub fn aggregate_plurality<C>(membership: &Membership, components: C) -> Self
@@ -66,7 +77,7 @@ impl Certificate {
 
         #[cfg(debug_assertions)]
         {
-            if certificate.power() < membership.plurality() {
+            if certificate.power(membership) < membership.plurality() {
                 panic!("Called `Certificate::aggregate` with an insufficient number of signers for a plurality");
             }
         }
@@ -82,7 +93,7 @@ impl Certificate {
 
         #[cfg(debug_assertions)]
         {
-            if certificate.power() < membership.quorum() {
+            if certificate.power(membership) < membership.quorum() {
                 panic!("Called `Certificate::aggregate` with an insufficient number of signers for a quorum");
             }
         }
@@ -90,8 +101,316 @@ impl Certificate {
         certificate
     }
 
-    pub fn power(&self) -> usize {
-        self.signers.iter().filter(|mask| *mask).count()
+    /// Like `aggregate`, but fails instead of panicking. Components are not
+    /// verified: only their signers and aggregation are checked.
+    pub fn try_aggregate<C>(
+        membership: &Membership,
+        components: C,
+    ) -> Result<Self, Top<CertificateError>>
+    where
+        C: IntoIterator<Item = (Identity, MultiSignature)>,
+    {
+        let mut signers = BitVec::from_elem(membership.servers().len(), false);
+        let mut signatures = Vec::new();
+
+        for (identity, signature) in components {
+            let index = Certificate::index(membership, identity)?;
+
+            if signers[index] {
+                return CertificateError::OverlappingSigners.fail().spot(here!());
+            }
+
+            signers.set(index, true);
+            signatures.push(signature);
+        }
+
+        let signature = MultiSignature::aggregate(signatures)
+            .pot(CertificateError::AggregationFailed, here!())?;
+
+        Ok(Certificate::multi(signers, signature))
+    }
+
+    pub fn try_aggregate_plurality<C>(
+        membership: &Membership,
+        components: C,
+    ) -> Result<Self, Top<CertificateError>>
+    where
+        C: IntoIterator<Item = (Identity, MultiSignature)>,
+    {
+        Certificate::try_aggregate_threshold(membership, components, membership.plurality())
+    }
+
+    pub fn try_aggregate_quorum<C>(
+        membership: &Membership,
+        components: C,
+    ) -> Result<Self, Top<CertificateError>>
+    where
+        C: IntoIterator<Item = (Identity, MultiSignature)>,
+    {
+        Certificate::try_aggregate_threshold(membership, components, membership.quorum())
+    }
+
+    fn try_aggregate_threshold<C>(
+        membership: &Membership,
+        components: C,
+        threshold: u64,
+    ) -> Result<Self, Top<CertificateError>>
+    where
+        C: IntoIterator<Item = (Identity, MultiSignature)>,
+    {
+        let certificate = Certificate::try_aggregate(membership, components)?;
+
+        if certificate.power(membership) < threshold {
+            return CertificateError::NotEnoughSigners.fail().spot(here!());
+        }
+
+        Ok(certificate)
+    }
+
+    /// Combines threshold signature shards into a constant-size certificate
+    /// for `threshold`. Shards are not verified (see `ThresholdShard::verify`):
+    /// combining an invalid shard yields an invalid certificate.
+    pub fn combine<C>(
+        membership: &Membership,
+        threshold: Threshold,
+        shards: C,
+    ) -> Result<Self, Top<CertificateError>>
+    where
+        C: IntoIterator<Item = (Identity, ThresholdShard)>,
+    {
+        let keys = membership
+            .threshold_keys()
+            .ok_or_else(|| CertificateError::ThresholdKeysMissing.into_top())
+            .spot(here!())?;
+
+        let mut shares = BTreeMap::new();
+
+        for (identity, shard) in shards {
+            let range = membership
+                .shares(identity)
+                .ok_or_else(|| CertificateError::ForeignSigner { identity }.into_top())
+                .spot(here!())?;
+
+            if shard.threshold() != threshold {
+                return CertificateError::CertificateInvalid.fail().spot(here!());
+            }
+
+            for (index, share) in shard.shares() {
+                if !range.contains(index) {
+                    return CertificateError::CertificateInvalid.fail().spot(here!());
+                }
+
+                shares.insert(*index, share);
+            }
+        }
+
+        let key_set = keys.key_set(threshold);
+
+        if shares.len() <= key_set.threshold() {
+            return CertificateError::NotEnoughSigners.fail().spot(here!());
+        }
+
+        let signature = key_set
+            .combine_signatures(shares)
+            .map_err(|_| CertificateError::AggregationFailed.into_top())
+            .spot(here!())?;
+
+        Ok(Certificate {
+            backend: Backend::Threshold {
+                threshold,
+                signature,
+            },
+        })
+    }
+
+    fn multi(signers: BitVec, signature: MultiSignature) -> Self {
+        Certificate {
+            backend: Backend::Multi { signers, signature },
+        }
+    }
+
+    /// Combines `self` and `other`, whose signers must be disjoint. Only
+    /// supported by multi-signature certificates.
+    pub fn merge(&self, other: &Certificate) -> Result<Certificate, Top<CertificateError>> {
+        let ((signers, signature), (other_signers, other_signature)) =
+            match (&self.backend, &other.backend) {
+                (
+                    Backend::Multi { signers, signature },
+                    Backend::Multi {
+                        signers: other_signers,
+                        signature: other_signature,
+                    },
+                ) => ((signers, signature), (other_signers, other_signature)),
+                _ => return CertificateError::BackendMismatch.fail().spot(here!()),
+            };
+
+        if signers.len() != other_signers.len() {
+            return CertificateError::CertificateInvalid.fail().spot(here!());
+        }
+
+        if signers
+            .iter()
+            .zip(other_signers.iter())
+            .any(|(left, right)| left && right)
+        {
+            return CertificateError::OverlappingSigners.fail().spot(here!());
+        }
+
+        let mut signers = signers.clone();
+        signers.or(other_signers);
+
+        let signature = MultiSignature::aggregate([*signature, *other_signature])
+            .pot(CertificateError::AggregationFailed, here!())?;
+
+        Ok(Certificate::multi(signers, signature))
+    }
+
+    /// Adds the `shard` of `identity`, which must not be a signer yet. As
+    /// with `try_aggregate`, `shard` is not verified. Only supported by
+    /// multi-signature certificates.
+    pub fn add(
+        &mut self,
+        membership: &Membership,
+        identity: Identity,
+        shard: MultiSignature,
+    ) -> Result<(), Top<CertificateError>> {
+        let (signers, signature) = match &mut self.backend {
+            Backend::Multi { signers, signature } => (signers, signature),
+            Backend::Threshold { .. } => {
+                return CertificateError::BackendMismatch.fail().spot(here!())
+            }
+        };
+
+        if !Certificate::matches(signers, membership) {
+            return CertificateError::CertificateInvalid.fail().spot(here!());
+        }
+
+        let index = Certificate::index(membership, identity)?;
+
+        if signers[index] {
+            return CertificateError::OverlappingSigners.fail().spot(here!());
+        }
+
+        *signature = MultiSignature::aggregate([*signature, shard])
+            .pot(CertificateError::AggregationFailed, here!())?;
+
+        signers.set(index, true);
+
+        Ok(())
+    }
+
+    fn index(membership: &Membership, identity: Identity) -> Result<usize, Top<CertificateError>> {
+        match membership.index(identity) {
+            Some(index) => Ok(index),
+            None => CertificateError::ForeignSigner { identity }
+                .fail()
+                .spot(here!()),
+        }
+    }
+
+    /// Total weight of the signers (0 if `self` does not match `membership`).
+    /// A threshold certificate has exactly the weight of its `Threshold` in
+    /// `membership`, provided its key set was dealt for that weight.
+    pub fn power(&self, membership: &Membership) -> u64 {
+        match &self.backend {
+            Backend::Multi { signers, .. } => {
+                if !Certificate::matches(signers, membership) {
+                    return 0;
+                }
+
+                signers
+                    .iter()
+                    .zip(membership.weights.iter())
+                    .filter(|(signed, _)| *signed)
+                    .map(|(_, weight)| *weight)
+                    .sum()
+            }
+            Backend::Threshold { threshold, .. } => {
+                Certificate::threshold_weight(membership, *threshold).unwrap_or(0)
+            }
+        }
+    }
+
+    /// Weight of the servers in `excluded` (e.g., proven to equivocate, see
+    /// `Equivocation`) among the signers of `self`. Threshold certificates do
+    /// not reveal their signers: every server in `excluded` is assumed to
+    /// have signed.
+    pub fn excluded_power(&self, membership: &Membership, excluded: &HashSet<Identity>) -> u64 {
+        match &self.backend {
+            Backend::Multi { signers, .. } => {
+                if !Certificate::matches(signers, membership) {
+                    return 0;
+                }
+
+                excluded
+                    .iter()
+                    .filter_map(|identity| membership.index(*identity))
+                    .filter(|index| signers[*index])
+                    .map(|index| membership.weights[index])
+                    .sum()
+            }
+            Backend::Threshold { .. } => excluded
+                .iter()
+                .map(|identity| membership.weight(*identity))
+                .sum(),
+        }
+    }
+
+    // Weight required by `threshold` in `membership`, if the key set of
+    // `threshold` requires exactly as many shares (see `ThresholdKeys::deal`)
+    fn threshold_weight(membership: &Membership, threshold: Threshold) -> Option<u64> {
+        let weight = match threshold {
+            Threshold::Plurality => membership.plurality(),
+            Threshold::Quorum => membership.quorum(),
+        };
+
+        let key_set = membership.threshold_keys()?.key_set(threshold);
+
+        if key_set.threshold() as u64 + 1 == weight {
+            Some(weight)
+        } else {
+            None
+        }
+    }
+
+    // Checks that `self` reaches `threshold`: a threshold certificate must be
+    // tagged with a `Threshold` whose weight is at least `threshold`
+    pub(in crate::membership) fn check_power(
+        &self,
+        membership: &Membership,
+        threshold: u64,
+    ) -> Result<(), Top<CertificateError>> {
+        match &self.backend {
+            Backend::Multi { .. } => {
+                if self.power(membership) < threshold {
+                    return CertificateError::NotEnoughSigners.fail().spot(here!());
+                }
+            }
+            Backend::Threshold { threshold: tag, .. } => {
+                match Certificate::threshold_weight(membership, *tag) {
+                    Some(weight) if weight >= threshold => {}
+                    _ => return CertificateError::ThresholdMismatch.fail().spot(here!()),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Signers and signature of a multi-signature certificate (see
+    // `CertificateVerifier`)
+    pub(in crate::membership) fn multi_parts(&self) -> Option<(&BitVec, &MultiSignature)> {
+        match &self.backend {
+            Backend::Multi { signers, signature } => Some((signers, signature)),
+            Backend::Threshold { .. } => None,
+        }
+    }
+
+    // `signers` comes from the network: make sure it has one bit per server
+    // (and no stray storage) before indexing it
+    pub(in crate::membership) fn matches(signers: &BitVec, membership: &Membership) -> bool {
+        signers.len() == membership.servers().len()
+            && signers.storage().len() == (signers.len() + 31) / 32
     }
 
     pub fn verify_raw<S>(
@@ -102,38 +421,157 @@ impl Certificate {
     where
         S: Statement,
     {
-        self.signature
-            .verify(
-                membership
-                    .servers()
-                    .values()
-                    .enumerate()
-                    .filter_map(|(index, card)| {
-                        if self.signers[index] {
-                            Some(card)
-                        } else {
-                            None
-                        }
-                    }),
-                message,
-            )
-            .pot(CertificateError::CertificateInvalid, here!())
+        match &self.backend {
+            Backend::Multi { signers, signature } => {
+                if !Certificate::matches(signers, membership) {
+                    return CertificateError::CertificateInvalid.fail();
+                }
+
+                signature
+                    .verify(
+                        membership
+                            .servers()
+                            .values()
+                            .enumerate()
+                            .filter_map(
+                                |(index, card)| {
+                                    if signers[index] {
+                                        Some(card)
+                                    } else {
+                                        None
+                                    }
+                                },
+                            ),
+                        message,
+                    )
+                    .pot(CertificateError::CertificateInvalid, here!())
+            }
+            Backend::Threshold {
+                threshold,
+                signature,
+            } => {
+                let keys = membership
+                    .threshold_keys()
+                    .ok_or_else(|| CertificateError::ThresholdKeysMissing.into_top())
+                    .spot(here!())?;
+
+                if keys
+                    .key_set(*threshold)
+                    .public_key()
+                    .verify(signature, threshold::message(message))
+                {
+                    Ok(())
+                } else {
+                    CertificateError::CertificateInvalid.fail().spot(here!())
+                }
+            }
+        }
+    }
+
+    /// Verifies many certificates at once, each against its own statement
+    /// and threshold (see `verify_threshold`). Multi-signature certificates
+    /// are checked together by a single randomized batched pairing check; if
+    /// it fails, they are checked one by one to pinpoint the invalid ones.
+    /// Returns one outcome per certificate, in order.
+    pub fn verify_batch<'a, S, B>(
+        membership: &Membership,
+        batch: B,
+    ) -> Vec<Result<(), Top<CertificateError>>>
+    where
+        S: 'a + Statement,
+        B: IntoIterator<Item = (&'a Certificate, &'a S, u64)>,
+    {
+        Certificate::verify_batch_with(membership, batch, |signers| {
+            Certificate::aggregate_keys(membership, signers)
+        })
+    }
+
+    // Like `verify_batch`, obtaining the aggregated public key of each set of
+    // multi-signature signers from `public_key` (see `CertificateVerifier`)
+    pub(in crate::membership) fn verify_batch_with<'a, S, B, K>(
+        membership: &Membership,
+        batch: B,
+        mut public_key: K,
+    ) -> Vec<Result<(), Top<CertificateError>>>
+    where
+        S: 'a + Statement,
+        B: IntoIterator<Item = (&'a Certificate, &'a S, u64)>,
+        K: FnMut(&BitVec) -> Result<MultiPublicKey, Top<CertificateError>>,
+    {
+        let mut outcomes = Vec::new();
+        let mut pending = Vec::new();
+
+        for (index, (certificate, statement, threshold)) in batch.into_iter().enumerate() {
+            if let Err(error) = certificate.check_power(membership, threshold) {
+                outcomes.push(Err(error));
+                continue;
+            }
+
+            let outcome = match &certificate.backend {
+                Backend::Multi { signers, signature } => {
+                    if Certificate::matches(signers, membership) {
+                        public_key(signers).map(|public_key| {
+                            pending.push((index, public_key, signature, statement));
+                        })
+                    } else {
+                        CertificateError::CertificateInvalid.fail().spot(here!())
+                    }
+                }
+                Backend::Threshold { .. } => certificate.verify_raw(membership, statement),
+            };
+
+            outcomes.push(outcome);
+        }
+
+        if pending.is_empty() {
+            return outcomes;
+        }
+
+        let batched = MultiSignature::batch_verify(
+            pending
+                .iter()
+                .map(|(_, public_key, signature, statement)| (*signature, public_key, *statement)),
+        );
+
+        if batched.is_err() {
+            for (index, public_key, signature, statement) in pending {
+                outcomes[index] = signature
+                    .verify_raw(iter::once(&public_key), statement)
+                    .pot(CertificateError::CertificateInvalid, here!());
+            }
+        }
+
+        outcomes
+    }
+
+    // Aggregates the multi-signature public keys of `signers`, which must
+    // match `membership`
+    pub(in crate::membership) fn aggregate_keys(
+        membership: &Membership,
+        signers: &BitVec,
+    ) -> Result<MultiPublicKey, Top<CertificateError>> {
+        MultiPublicKey::aggregate(
+            membership
+                .servers()
+                .values()
+                .enumerate()
+                .filter(|(index, _)| signers[*index])
+                .map(|(_, card)| card.multi_public_key()),
+        )
+        .pot(CertificateError::CertificateInvalid, here!())
     }
 
     pub fn verify_threshold<S>(
         &self,
         membership: &Membership,
         message: &S,
-        threshold: usize,
+        threshold: u64,
     ) -> Result<(), Top<CertificateError>>
     where
         S: Statement,
     {
-        if self.power() >= threshold {
-            self.verify_raw(membership, message)
-        } else {
-            CertificateError::NotEnoughSigners.fail()
-        }
+        self.check_power(membership, threshold)?;
+        self.verify_raw(membership, message)
     }
 
     pub fn verify_plurality<S>(
@@ -158,3 +596,129 @@ impl Certificate {
         self.verify_threshold(membership, message, membership.quorum())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{crypto::Domain, passepartout::Passepartout, server::WitnessStatement};
+
+    use talk::crypto::{primitives::hash, KeyChain};
+
+    #[test]
+    fn merge() {
+        let passepartout = Passepartout::random(4);
+        let (membership, _) = passepartout.system(4);
+
+        let statement = WitnessStatement::new(Domain::default(), hash::hash(&0u64).unwrap());
+
+        let shards = membership
+            .servers()
+            .keys()
+            .map(|identity| {
+                let shard = passepartout
+                    .keychain(*identity)
+                    .multisign(&statement)
+                    .unwrap();
+
+                (*identity, shard)
+            })
+            .collect::<Vec<_>>();
+
+        let left = Certificate::try_aggregate(&membership, shards[..2].iter().cloned()).unwrap();
+        let right = Certificate::try_aggregate(&membership, shards[2..3].iter().cloned()).unwrap();
+
+        let error = Certificate::try_aggregate_quorum(&membership, shards[..2].iter().cloned())
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), CertificateError::NotEnoughSigners));
+
+        let error = left.merge(&left).err().unwrap();
+        assert!(matches!(error.top(), CertificateError::OverlappingSigners));
+
+        let mut certificate = left.merge(&right).unwrap();
+        certificate.verify_quorum(&membership, &statement).unwrap();
+
+        let error = certificate
+            .add(&membership, shards[0].0, shards[0].1)
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), CertificateError::OverlappingSigners));
+
+        let identity = KeyChain::random().keycard().identity();
+        let error = certificate
+            .add(&membership, identity, shards[3].1)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.top(),
+            CertificateError::ForeignSigner { .. }
+        ));
+
+        certificate
+            .add(&membership, shards[3].0, shards[3].1)
+            .unwrap();
+        assert_eq!(certificate.power(&membership), 4);
+        certificate.verify_quorum(&membership, &statement).unwrap();
+    }
+
+    #[test]
+    fn verify_batch() {
+        let passepartout = Passepartout::random(4);
+        let (membership, _) = passepartout.system(4);
+
+        let statements = (0..4u64)
+            .map(|index| WitnessStatement::new(Domain::default(), hash::hash(&index).unwrap()))
+            .collect::<Vec<_>>();
+
+        let certificates = statements
+            .iter()
+            .map(|statement| {
+                Certificate::aggregate(
+                    &membership,
+                    membership.servers().keys().take(3).map(|identity| {
+                        let shard = passepartout
+                            .keychain(*identity)
+                            .multisign(statement)
+                            .unwrap();
+
+                        (*identity, shard)
+                    }),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let outcomes = Certificate::verify_batch(
+            &membership,
+            certificates
+                .iter()
+                .zip(statements.iter())
+                .map(|(certificate, statement)| (certificate, statement, membership.quorum())),
+        );
+
+        assert!(outcomes.iter().all(Result::is_ok));
+
+        // Certificate 1 is checked against the wrong statement, certificate 3
+        // against an unreachable threshold
+        let outcomes = Certificate::verify_batch(
+            &membership,
+            [
+                (&certificates[0], &statements[0], membership.quorum()),
+                (&certificates[1], &statements[2], membership.quorum()),
+                (&certificates[2], &statements[2], membership.quorum()),
+                (&certificates[3], &statements[3], 4),
+            ],
+        );
+
+        assert!(outcomes[0].is_ok());
+        assert!(matches!(
+            outcomes[1].as_ref().err().unwrap().top(),
+            CertificateError::CertificateInvalid
+        ));
+        assert!(outcomes[2].is_ok());
+        assert!(matches!(
+            outcomes[3].as_ref().err().unwrap().top(),
+            CertificateError::NotEnoughSigners
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use bit_vec::BitVec;
+
+use crate::membership::{Certificate, CertificateError, Membership};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use lru::LruCache;
+
+use std::{iter, sync::Mutex};
+
+use talk::crypto::{primitives::multi::PublicKey as MultiPublicKey, Statement};
+
+const DEFAULT_CACHE: usize = 1024;
+
+/// Verifies `Certificate`s against a fixed `Membership`, with the same
+/// semantics as `Certificate::verify_*`.
+///
+/// The aggregated public key of every multi-signature signer set is cached:
+/// certificates from a recently seen signer set cost a single pairing check,
+/// instead of filtering and aggregating the membership's keys again.
+pub struct CertificateVerifier {
+    membership: Membership,
+    cache: Mutex<LruCache<BitVec, MultiPublicKey>>,
+}
+
+impl CertificateVerifier {
+    pub fn new(membership: Membership) -> Self {
+        CertificateVerifier::with_cache(membership, DEFAULT_CACHE)
+    }
+
+    /// Caches the aggregated public keys of up to `cache` signer sets.
+    pub fn with_cache(membership: Membership, cache: usize) -> Self {
+        let cache = Mutex::new(LruCache::new(cache));
+
+        CertificateVerifier { membership, cache }
+    }
+
+    pub fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    pub fn verify_raw<S>(
+        &self,
+        certificate: &Certificate,
+        message: &S,
+    ) -> Result<(), Top<CertificateError>>
+    where
+        S: Statement,
+    {
+        // Threshold certificates are already verified against a single key
+        let (signers, signature) = match certificate.multi_parts() {
+            Some(parts) => parts,
+            None => return certificate.verify_raw(&self.membership, message),
+        };
+
+        if !Certificate::matches(signers, &self.membership) {
+            return CertificateError::CertificateInvalid.fail().spot(here!());
+        }
+
+        let public_key = self.public_key(signers)?;
+
+        signature
+            .verify_raw(iter::once(&public_key), message)
+            .pot(CertificateError::CertificateInvalid, here!())
+    }
+
+    pub fn verify_threshold<S>(
+        &self,
+        certificate: &Certificate,
+        message: &S,
+        threshold: u64,
+    ) -> Result<(), Top<CertificateError>>
+    where
+        S: Statement,
+    {
+        certificate.check_power(&self.membership, threshold)?;
+        self.verify_raw(certificate, message)
+    }
+
+    pub fn verify_plurality<S>(
+        &self,
+        certificate: &Certificate,
+        message: &S,
+    ) -> Result<(), Top<CertificateError>>
+    where
+        S: Statement,
+    {
+        self.verify_threshold(certificate, message, self.membership.plurality())
+    }
+
+    pub fn verify_quorum<S>(
+        &self,
+        certificate: &Certificate,
+        message: &S,
+    ) -> Result<(), Top<CertificateError>>
+    where
+        S: Statement,
+    {
+        self.verify_threshold(certificate, message, self.membership.quorum())
+    }
+
+    /// Like `Certificate::verify_batch`, using cached public keys.
+    pub fn verify_batch<'a, S, B>(&self, batch: B) -> Vec<Result<(), Top<CertificateError>>>
+    where
+        S: 'a + Statement,
+        B: IntoIterator<Item = (&'a Certificate, &'a S, u64)>,
+    {
+        Certificate::verify_batch_with(&self.membership, batch, |signers| self.public_key(signers))
+    }
+
+    fn public_key(&self, signers: &BitVec) -> Result<MultiPublicKey, Top<CertificateError>> {
+        if let Some(public_key) = self.cache.lock().unwrap().get(signers) {
+            return Ok(*public_key);
+        }
+
+        // Aggregate outside of the lock: concurrent misses on the same
+        // signers only waste work
+        let public_key = Certificate::aggregate_keys(&self.membership, signers)?;
+
+        self.cache.lock().unwrap().put(signers.clone(), public_key);
+
+        Ok(public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{crypto::Domain, passepartout::Passepartout, server::WitnessStatement};
+
+    use talk::crypto::primitives::hash;
+
+    #[test]
+    fn cache() {
+        let passepartout = Passepartout::random(4);
+        let (membership, _) = passepartout.system(4);
+
+        let statement = WitnessStatement::new(Domain::default(), hash::hash(&0u64).unwrap());
+
+        let certificate = Certificate::aggregate_quorum(
+            &membership,
+            membership.servers().keys().take(3).map(|identity| {
+                let shard = passepartout
+                    .keychain(*identity)
+                    .multisign(&statement)
+                    .unwrap();
+
+                (*identity, shard)
+            }),
+        );
+
+        let verifier = CertificateVerifier::new(membership);
+
+        for _ in 0..2 {
+            verifier.verify_quorum(&certificate, &statement).unwrap();
+            assert_eq!(verifier.cache.lock().unwrap().len(), 1);
+        }
+
+        let other = WitnessStatement::new(Domain::default(), hash::hash(&1u64).unwrap());
+        assert!(verifier.verify_quorum(&certificate, &other).is_err());
+
+        certificate
+            .verify_quorum(verifier.membership(), &statement)
+            .unwrap();
+    }
+}
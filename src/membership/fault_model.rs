@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+/// Which faults a `Membership` tolerates, as a function of its total weight
+/// `n`. Determines `f`, the largest faulty weight tolerated, and from it the
+/// `plurality` and `quorum` thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FaultModel {
+    /// Byzantine faults, `n >= 3f + 1`
+    Byzantine,
+    /// Byzantine faults, `n >= 5f + 1` (enables fast paths)
+    ByzantineFastPath,
+    /// Crash faults only, `n >= 2f + 1`
+    Crash,
+}
+
+impl FaultModel {
+    /// Largest faulty weight tolerated out of `total`.
+    pub fn faults(&self, total: u64) -> u64 {
+        let total = total.saturating_sub(1);
+
+        match self {
+            FaultModel::Byzantine => total / 3,
+            FaultModel::ByzantineFastPath => total / 5,
+            FaultModel::Crash => total / 2,
+        }
+    }
+
+    /// Smallest weight guaranteed to include a correct server.
+    pub fn plurality(&self, total: u64) -> u64 {
+        match self {
+            FaultModel::Byzantine | FaultModel::ByzantineFastPath => self.faults(total) + 1,
+            // Crashed servers never sign
+            FaultModel::Crash => 1,
+        }
+    }
+
+    /// Largest weight guaranteed to be available (all but the faulty weight).
+    /// Any two quorums intersect in at least a plurality.
+    pub fn quorum(&self, total: u64) -> u64 {
+        total - self.faults(total)
+    }
+}
+
+impl Default for FaultModel {
+    fn default() -> Self {
+        FaultModel::Byzantine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        let cases = [
+            (FaultModel::Byzantine, 4, (1, 2, 3)),
+            (FaultModel::Byzantine, 10, (3, 4, 7)),
+            (FaultModel::ByzantineFastPath, 11, (2, 3, 9)),
+            (FaultModel::Crash, 5, (2, 1, 3)),
+            (FaultModel::Crash, 1, (0, 1, 1)),
+        ];
+
+        for (model, total, thresholds) in cases {
+            assert_eq!(
+                (
+                    model.faults(total),
+                    model.plurality(total),
+                    model.quorum(total)
+                ),
+                thresholds
+            );
+        }
+    }
+}
@@ -1,58 +1,348 @@
+use crate::{
+    crypto::Domain,
+    membership::{FaultModel, ThresholdKeys},
+    persistence::{self, FileKind, PersistenceError, LEGACY_VERSION},
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
 use serde::{Deserialize, Serialize};
 
-use std::{collections::BTreeMap, fs};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+    path::Path,
+};
 
 use talk::crypto::{Identity, KeyCard};
 
+#[derive(Doom)]
+pub enum MembershipError {
+    #[doom(description("Failed to load membership"))]
+    LoadFailed,
+    #[doom(description("Not enough servers"))]
+    NotEnoughServers,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "Serialized")]
 pub struct Membership {
     pub(in crate::membership) servers: BTreeMap<Identity, KeyCard>,
+    // Voting power of each server, in the same order as `servers`
+    pub(in crate::membership) weights: Vec<u64>,
+    fault_model: FaultModel,
+    threshold_keys: Option<ThresholdKeys>,
+    domain: Domain,
+    // Position of each server in `servers`, and total weight of the servers
+    // before it: both rebuilt from `servers` and `weights` on deserialization
+    #[serde(skip)]
+    indices: HashMap<Identity, usize>,
+    #[serde(skip)]
+    offsets: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Membership")]
+struct Serialized {
+    servers: BTreeMap<Identity, KeyCard>,
+    weights: Vec<u64>,
+    fault_model: FaultModel,
+    threshold_keys: Option<ThresholdKeys>,
+    domain: Domain,
 }
 
+// Legacy files (written before the header was introduced) only list the
+// servers' `KeyCard`s, each with weight 1.
+const VERSION: u16 = 1;
+
 impl Membership {
+    /// A membership where every server has weight 1.
     pub(crate) fn from_servers<K>(servers: K) -> Self
     where
         K: IntoIterator<Item = KeyCard>,
+    {
+        Membership::from_weighted_servers(servers.into_iter().map(|keycard| (keycard, 1)))
+    }
+
+    pub fn from_weighted_servers<K>(servers: K) -> Self
+    where
+        K: IntoIterator<Item = (KeyCard, u64)>,
     {
         let servers = servers
             .into_iter()
-            .map(|keycard| (keycard.identity(), keycard))
+            .map(|(keycard, weight)| (keycard.identity(), (keycard, weight)))
             .collect::<BTreeMap<_, _>>();
 
-        Membership { servers }
+        let weights = servers
+            .values()
+            .map(|(_, weight)| *weight)
+            .collect::<Vec<_>>();
+
+        let indices = servers
+            .keys()
+            .enumerate()
+            .map(|(index, identity)| (*identity, index))
+            .collect();
+
+        let offsets = weights
+            .iter()
+            .scan(0, |offset, weight| {
+                let start = *offset;
+                *offset += weight;
+                Some(start)
+            })
+            .collect();
+
+        let servers = servers
+            .into_iter()
+            .map(|(identity, (keycard, _))| (identity, keycard))
+            .collect();
+
+        Membership {
+            servers,
+            weights,
+            fault_model: FaultModel::default(),
+            threshold_keys: None,
+            domain: Domain::default(),
+            indices,
+            offsets,
+        }
     }
 
-    pub fn load(path: &str) -> Membership {
-        let servers = bincode::deserialize::<Vec<_>>(fs::read(path).unwrap().as_slice()).unwrap();
-        Membership::from_servers(servers)
+    pub fn with_fault_model(mut self, fault_model: FaultModel) -> Self {
+        self.fault_model = fault_model;
+        self
     }
 
-    pub fn load_exact(path: &str, size: usize) -> Membership {
-        let mut servers =
-            bincode::deserialize::<Vec<_>>(fs::read(path).unwrap().as_slice()).unwrap();
+    /// Attaches `threshold_keys`, which must have been dealt for `self` (see
+    /// `ThresholdKeys::deal`), enabling threshold certificates.
+    pub fn with_threshold_keys(mut self, threshold_keys: ThresholdKeys) -> Self {
+        self.threshold_keys = Some(threshold_keys);
+        self
+    }
 
-        if servers.len() < size {
-            panic!("`load_exact` could not load enough servers");
+    /// Binds every statement signed or verified under `self` to `domain`.
+    pub fn with_domain(mut self, domain: Domain) -> Self {
+        self.domain = domain;
+        self
+    }
+
+    pub fn load<P>(path: P) -> Result<Membership, Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = persistence::read(path)?;
+        let (version, body) = persistence::open(FileKind::Membership, bytes.as_slice())?;
+
+        let (fault_model, servers, threshold_keys, domain) = match version {
+            LEGACY_VERSION => {
+                let servers = persistence::deserialize_body::<Vec<KeyCard>>(version, body)?;
+                let servers = servers.into_iter().map(|keycard| (keycard, 1)).collect();
+
+                (FaultModel::Byzantine, servers, None, Domain::default())
+            }
+            VERSION => persistence::deserialize(body)?,
+            version => {
+                return PersistenceError::UnsupportedVersion { version }
+                    .fail()
+                    .spot(here!())
+            }
+        };
+
+        let mut membership = Membership::from_weighted_servers::<Vec<_>>(servers)
+            .with_fault_model(fault_model)
+            .with_domain(domain);
+
+        membership.threshold_keys = threshold_keys;
+        Ok(membership)
+    }
+
+    pub fn load_exact<P>(path: P, size: usize) -> Result<Membership, Top<MembershipError>>
+    where
+        P: AsRef<Path>,
+    {
+        let membership = Membership::load(path).pot(MembershipError::LoadFailed, here!())?;
+
+        if membership.servers.len() < size {
+            return MembershipError::NotEnoughServers.fail().spot(here!());
         }
 
-        servers.truncate(size);
-        Membership::from_servers(servers)
+        if membership.servers.len() == size {
+            return Ok(membership);
+        }
+
+        // Threshold keys were dealt for the whole membership: they are dropped
+        let servers = membership
+            .servers
+            .values()
+            .cloned()
+            .zip(membership.weights.iter().copied())
+            .take(size);
+
+        Ok(Membership::from_weighted_servers(servers)
+            .with_fault_model(membership.fault_model)
+            .with_domain(membership.domain))
     }
 
-    pub fn save(&self, path: &str) {
-        let servers = self.servers.values().cloned().collect::<Vec<_>>();
-        fs::write(path, bincode::serialize(&servers).unwrap().as_slice()).unwrap();
+    pub fn save<P>(&self, path: P) -> Result<(), Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let servers = self
+            .servers
+            .values()
+            .cloned()
+            .zip(self.weights.iter().copied())
+            .collect::<Vec<_>>();
+
+        let body = persistence::serialize(&(
+            self.fault_model,
+            servers,
+            &self.threshold_keys,
+            self.domain,
+        ))?;
+
+        persistence::write(path, FileKind::Membership, VERSION, body.as_slice())
     }
 
     pub fn servers(&self) -> &BTreeMap<Identity, KeyCard> {
         &self.servers
     }
 
-    pub fn plurality(&self) -> usize {
-        (self.servers.len() - 1) / 3 + 1
+    /// Position of `identity` in `servers` (`None` if `identity` is not a server).
+    pub(in crate::membership) fn index(&self, identity: Identity) -> Option<usize> {
+        self.indices.get(&identity).copied()
     }
 
-    pub fn quorum(&self) -> usize {
-        self.servers.len() - self.plurality() + 1
+    /// Voting power of `identity` (0 if `identity` is not a server).
+    pub fn weight(&self, identity: Identity) -> u64 {
+        self.index(identity)
+            .map(|index| self.weights[index])
+            .unwrap_or(0)
+    }
+
+    /// Indices of the threshold key shares of `identity`: one per unit of
+    /// weight, in `servers` order (`None` if `identity` is not a server).
+    pub(in crate::membership) fn shares(&self, identity: Identity) -> Option<Range<usize>> {
+        let index = self.index(identity)?;
+
+        let start = self.offsets[index] as usize;
+        Some(start..(start + self.weights[index] as usize))
+    }
+
+    pub fn threshold_keys(&self) -> Option<&ThresholdKeys> {
+        self.threshold_keys.as_ref()
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        self.weights.iter().sum()
+    }
+
+    pub fn domain(&self) -> Domain {
+        self.domain
+    }
+
+    pub fn fault_model(&self) -> FaultModel {
+        self.fault_model
+    }
+
+    /// Largest faulty weight tolerated (see `FaultModel::faults`).
+    pub fn faults(&self) -> u64 {
+        self.fault_model.faults(self.total_weight())
+    }
+
+    /// Smallest weight guaranteed to include a correct server (see
+    /// `FaultModel::plurality`). With unit weights and the default
+    /// `FaultModel`, `f + 1`.
+    pub fn plurality(&self) -> u64 {
+        self.fault_model.plurality(self.total_weight())
+    }
+
+    /// Weight guaranteed to be available, any two quorums intersecting in a
+    /// plurality (see `FaultModel::quorum`). With unit weights and the
+    /// default `FaultModel`, `2f + 1`.
+    pub fn quorum(&self) -> u64 {
+        self.fault_model.quorum(self.total_weight())
+    }
+}
+
+impl From<Serialized> for Membership {
+    fn from(serialized: Serialized) -> Self {
+        let servers = serialized
+            .servers
+            .into_values()
+            .zip(serialized.weights)
+            .collect::<Vec<_>>();
+
+        let mut membership = Membership::from_weighted_servers(servers)
+            .with_fault_model(serialized.fault_model)
+            .with_domain(serialized.domain);
+
+        membership.threshold_keys = serialized.threshold_keys;
+        membership
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use talk::crypto::KeyChain;
+
+    #[test]
+    fn weights() {
+        let keycards = (0..4)
+            .map(|_| KeyChain::random().keycard())
+            .collect::<Vec<_>>();
+
+        let membership = Membership::from_servers(keycards.clone());
+        assert_eq!((membership.plurality(), membership.quorum()), (2, 3));
+
+        membership.save("assets/membership-weights.bin").unwrap();
+        let loaded = Membership::load("assets/membership-weights.bin").unwrap();
+        assert_eq!(loaded.weights, vec![1; 4]);
+
+        let weighted =
+            Membership::from_weighted_servers(keycards.iter().cloned().zip([1, 1, 1, 7]));
+
+        assert_eq!(weighted.total_weight(), 10);
+        assert_eq!(weighted.weight(keycards[3].identity()), 7);
+        assert_eq!((weighted.plurality(), weighted.quorum()), (4, 7));
+        assert_eq!(
+            weighted
+                .shares(keycards[3].identity())
+                .map(|shares| shares.len()),
+            Some(7)
+        );
+
+        let decoded =
+            bincode::deserialize::<Membership>(&bincode::serialize(&weighted).unwrap()).unwrap();
+        assert_eq!(decoded.weight(keycards[3].identity()), 7);
+
+        weighted.save("assets/membership-weights.bin").unwrap();
+        let loaded = Membership::load("assets/membership-weights.bin").unwrap();
+
+        assert!(loaded.servers().keys().eq(weighted.servers().keys()));
+        assert_eq!(loaded.weights, weighted.weights);
+
+        let crash = weighted.with_fault_model(FaultModel::Crash);
+        assert_eq!((crash.plurality(), crash.quorum()), (1, 6));
+
+        crash.save("assets/membership-weights.bin").unwrap();
+        let loaded = Membership::load("assets/membership-weights.bin").unwrap();
+
+        assert_eq!(loaded.fault_model(), FaultModel::Crash);
+        assert_eq!(loaded.weights, crash.weights);
+
+        let domain = Domain::new(7, 1);
+        crash
+            .with_domain(domain)
+            .save("assets/membership-weights.bin")
+            .unwrap();
+
+        let loaded = Membership::load_exact("assets/membership-weights.bin", 3).unwrap();
+        assert_eq!(loaded.domain(), domain);
+        assert_eq!(loaded.fault_model(), FaultModel::Crash);
+        assert_eq!(loaded.total_weight(), 3);
     }
 }
@@ -1,5 +1,17 @@
 mod certificate;
+mod certificate_verifier;
+mod fault_model;
 mod membership;
+mod threshold;
+mod threshold_key_chain;
+mod threshold_keys;
+mod threshold_shard;
 
 pub use certificate::{Certificate, CertificateError};
-pub use membership::Membership;
+pub use certificate_verifier::CertificateVerifier;
+pub use fault_model::FaultModel;
+pub use membership::{Membership, MembershipError};
+pub use threshold::Threshold;
+pub use threshold_key_chain::ThresholdKeyChain;
+pub use threshold_keys::{ThresholdKeys, ThresholdKeysError};
+pub use threshold_shard::ThresholdShard;
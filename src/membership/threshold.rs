@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::Statement;
+
+/// The `Membership` threshold a threshold signature attests to. Each
+/// threshold has its own key set (see `ThresholdKeys`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Threshold {
+    Plurality,
+    Quorum,
+}
+
+// Threshold signatures sign raw bytes: like `talk`'s signatures, they cover
+// the statement's header along with the statement itself
+pub(in crate::membership) fn message<S>(statement: &S) -> Vec<u8>
+where
+    S: Statement,
+{
+    bincode::serialize(&(S::HEADER, statement)).unwrap()
+}
@@ -0,0 +1,113 @@
+use blsttc::{serde_impl::SerdeSecret, SecretKeyShare};
+
+use crate::{
+    membership::{threshold, Membership, Threshold, ThresholdShard},
+    persistence::{self, FileKind, PersistenceError},
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use serde::{Deserialize, Serialize};
+
+use std::path::Path;
+
+use talk::crypto::{Identity, Statement};
+
+/// The secret key shares of a server (see `ThresholdKeys`). Files written by
+/// `save` are not encrypted.
+#[derive(Serialize, Deserialize)]
+pub struct ThresholdKeyChain {
+    identity: Identity,
+    plurality: Vec<(usize, SerdeSecret<SecretKeyShare>)>,
+    quorum: Vec<(usize, SerdeSecret<SecretKeyShare>)>,
+}
+
+const VERSION: u16 = 1;
+
+impl ThresholdKeyChain {
+    pub(in crate::membership) fn new(
+        identity: Identity,
+        plurality: Vec<(usize, SerdeSecret<SecretKeyShare>)>,
+        quorum: Vec<(usize, SerdeSecret<SecretKeyShare>)>,
+    ) -> Self {
+        ThresholdKeyChain {
+            identity,
+            plurality,
+            quorum,
+        }
+    }
+
+    pub fn load<P>(path: P) -> Result<Self, Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = persistence::read(path)?;
+        let (version, body) = persistence::open(FileKind::ThresholdKeyChain, bytes.as_slice())?;
+
+        match version {
+            VERSION => persistence::deserialize(body),
+            version => PersistenceError::UnsupportedVersion { version }
+                .fail()
+                .spot(here!()),
+        }
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let body = persistence::serialize(self)?;
+        persistence::write(path, FileKind::ThresholdKeyChain, VERSION, body.as_slice())
+    }
+
+    /// The server these shares belong to.
+    pub fn identity(&self) -> Identity {
+        self.identity
+    }
+
+    pub fn sign<S>(&self, threshold: Threshold, statement: &S) -> ThresholdShard
+    where
+        S: Statement,
+    {
+        let message = threshold::message(statement);
+
+        let shares = self
+            .shares(threshold)
+            .iter()
+            .map(|(index, share)| (*index, share.sign(message.as_slice())))
+            .collect();
+
+        ThresholdShard::new(threshold, shares)
+    }
+
+    /// Checks that `self` holds exactly the shares `membership`'s threshold
+    /// keys assign to `self.identity()`.
+    pub fn matches(&self, membership: &Membership) -> bool {
+        let (keys, range) = match (
+            membership.threshold_keys(),
+            membership.shares(self.identity),
+        ) {
+            (Some(keys), Some(range)) => (keys, range),
+            _ => return false,
+        };
+
+        [Threshold::Plurality, Threshold::Quorum]
+            .into_iter()
+            .all(|threshold| {
+                let key_set = keys.key_set(threshold);
+                let shares = self.shares(threshold);
+
+                shares.iter().map(|(index, _)| *index).eq(range.clone())
+                    && shares.iter().all(|(index, share)| {
+                        share.public_key_share() == key_set.public_key_share(*index)
+                    })
+            })
+    }
+
+    fn shares(&self, threshold: Threshold) -> &[(usize, SerdeSecret<SecretKeyShare>)] {
+        match threshold {
+            Threshold::Plurality => self.plurality.as_slice(),
+            Threshold::Quorum => self.quorum.as_slice(),
+        }
+    }
+}
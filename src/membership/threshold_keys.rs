@@ -0,0 +1,179 @@
+use blsttc::{serde_impl::SerdeSecret, PublicKeySet, SecretKeySet};
+
+use crate::membership::{Membership, Threshold, ThresholdKeyChain};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use rand::Rng;
+
+use serde::{Deserialize, Serialize};
+
+/// Public keys of a threshold signature setup for a `Membership`, with one
+/// key set per `Threshold`.
+///
+/// Every server holds one key share per unit of weight, so that the shares of
+/// any set of servers reaching a threshold combine into a single signature
+/// (see `Certificate::combine`). The number of shares is the membership's
+/// total weight, which `deal` bounds to `MAX_WEIGHT`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ThresholdKeys {
+    plurality: PublicKeySet,
+    quorum: PublicKeySet,
+}
+
+// Signing, combining and verifying shares all grow with the number of shares
+const MAX_WEIGHT: u64 = 1024;
+
+#[derive(Doom)]
+pub enum ThresholdKeysError {
+    #[doom(description("Total weight too large for threshold keys: {}", weight))]
+    WeightTooLarge { weight: u64 },
+}
+
+impl ThresholdKeys {
+    /// Generates keys for `membership` with a trusted dealer. Returns the
+    /// public keys, along with one `ThresholdKeyChain` per server (in
+    /// `Membership` order). The dealer learns every share: it must discard
+    /// them once they are distributed. Fails if the total weight of
+    /// `membership` exceeds `MAX_WEIGHT` (1024) shares.
+    pub fn deal<R>(
+        membership: &Membership,
+        rng: &mut R,
+    ) -> Result<(Self, Vec<ThresholdKeyChain>), Top<ThresholdKeysError>>
+    where
+        R: Rng,
+    {
+        let weight = membership.total_weight();
+
+        if weight > MAX_WEIGHT {
+            return ThresholdKeysError::WeightTooLarge { weight }
+                .fail()
+                .spot(here!());
+        }
+
+        // A key set of threshold `t` requires `t + 1` shares to sign
+        let plurality =
+            SecretKeySet::random(membership.plurality().saturating_sub(1) as usize, rng);
+        let quorum = SecretKeySet::random(membership.quorum().saturating_sub(1) as usize, rng);
+
+        let keychains = membership
+            .servers()
+            .keys()
+            .map(|identity| {
+                let shares = membership.shares(*identity).unwrap();
+
+                let deal = |key_set: &SecretKeySet| {
+                    shares
+                        .clone()
+                        .map(|index| (index, SerdeSecret(key_set.secret_key_share(index))))
+                        .collect()
+                };
+
+                ThresholdKeyChain::new(*identity, deal(&plurality), deal(&quorum))
+            })
+            .collect();
+
+        let keys = ThresholdKeys {
+            plurality: plurality.public_keys(),
+            quorum: quorum.public_keys(),
+        };
+
+        Ok((keys, keychains))
+    }
+
+    pub fn key_set(&self, threshold: Threshold) -> &PublicKeySet {
+        match threshold {
+            Threshold::Plurality => &self.plurality,
+            Threshold::Quorum => &self.quorum,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        crypto::Domain,
+        membership::{Certificate, CertificateError},
+        server::WitnessStatement,
+    };
+
+    use talk::crypto::{primitives::hash, KeyChain};
+
+    #[test]
+    fn combine() {
+        let keycards = (0..4)
+            .map(|_| KeyChain::random().keycard())
+            .collect::<Vec<_>>();
+
+        let membership = Membership::from_weighted_servers(keycards.into_iter().zip([1, 1, 1, 2]));
+        assert_eq!((membership.plurality(), membership.quorum()), (2, 4));
+
+        let (keys, keychains) = ThresholdKeys::deal(&membership, &mut rand::thread_rng()).unwrap();
+        let membership = membership.with_threshold_keys(keys);
+
+        assert!(keychains
+            .iter()
+            .all(|keychain| keychain.matches(&membership)));
+
+        membership.save("assets/membership-threshold.bin").unwrap();
+        let membership = Membership::load("assets/membership-threshold.bin").unwrap();
+
+        let statement = WitnessStatement::new(Domain::default(), hash::hash(&0u64).unwrap());
+
+        let shards = keychains
+            .iter()
+            .map(|keychain| {
+                let shard = keychain.sign(Threshold::Quorum, &statement);
+                shard
+                    .verify(&membership, keychain.identity(), &statement)
+                    .unwrap();
+
+                (keychain.identity(), shard)
+            })
+            .collect::<Vec<_>>();
+
+        // The last server alone carries weight 2
+        let error = Certificate::combine(&membership, Threshold::Quorum, shards[1..3].to_vec())
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), CertificateError::NotEnoughSigners));
+
+        let certificate =
+            Certificate::combine(&membership, Threshold::Quorum, shards[1..].to_vec()).unwrap();
+
+        assert_eq!(certificate.power(&membership), 4);
+        certificate.verify_quorum(&membership, &statement).unwrap();
+        certificate
+            .verify_plurality(&membership, &statement)
+            .unwrap();
+
+        let other = WitnessStatement::new(Domain::default(), hash::hash(&1u64).unwrap());
+        assert!(certificate.verify_quorum(&membership, &other).is_err());
+
+        // A plurality certificate does not attest to a quorum
+        let certificate = Certificate::combine(
+            &membership,
+            Threshold::Plurality,
+            keychains[2..].iter().map(|keychain| {
+                (
+                    keychain.identity(),
+                    keychain.sign(Threshold::Plurality, &statement),
+                )
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(certificate.power(&membership), 2);
+        certificate
+            .verify_plurality(&membership, &statement)
+            .unwrap();
+
+        let error = certificate
+            .verify_quorum(&membership, &statement)
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), CertificateError::ThresholdMismatch));
+    }
+}
@@ -0,0 +1,73 @@
+use blsttc::SignatureShare;
+
+use crate::membership::{threshold, CertificateError, Membership, Threshold};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::{Identity, Statement};
+
+/// A server's signature shares for a `Threshold`, one per unit of weight (see
+/// `ThresholdKeyChain::sign`).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ThresholdShard {
+    threshold: Threshold,
+    shares: Vec<(usize, SignatureShare)>,
+}
+
+impl ThresholdShard {
+    pub(in crate::membership) fn new(
+        threshold: Threshold,
+        shares: Vec<(usize, SignatureShare)>,
+    ) -> Self {
+        ThresholdShard { threshold, shares }
+    }
+
+    pub fn threshold(&self) -> Threshold {
+        self.threshold
+    }
+
+    pub(in crate::membership) fn shares(&self) -> &[(usize, SignatureShare)] {
+        self.shares.as_slice()
+    }
+
+    /// Checks that `self` holds a valid share of `statement` for every key
+    /// share `membership` assigns to `identity`.
+    pub fn verify<S>(
+        &self,
+        membership: &Membership,
+        identity: Identity,
+        statement: &S,
+    ) -> Result<(), Top<CertificateError>>
+    where
+        S: Statement,
+    {
+        let keys = membership
+            .threshold_keys()
+            .ok_or_else(|| CertificateError::ThresholdKeysMissing.into_top())
+            .spot(here!())?;
+
+        let range = membership
+            .shares(identity)
+            .ok_or_else(|| CertificateError::ForeignSigner { identity }.into_top())
+            .spot(here!())?;
+
+        if !self.shares.iter().map(|(index, _)| *index).eq(range) {
+            return CertificateError::CertificateInvalid.fail().spot(here!());
+        }
+
+        let key_set = keys.key_set(self.threshold);
+        let message = threshold::message(statement);
+
+        if self.shares.iter().all(|(index, share)| {
+            key_set
+                .public_key_share(*index)
+                .verify(share, message.as_slice())
+        }) {
+            Ok(())
+        } else {
+            CertificateError::CertificateInvalid.fail().spot(here!())
+        }
+    }
+}
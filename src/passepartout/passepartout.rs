@@ -1,21 +1,58 @@
-use crate::{directory::Directory, membership::Membership};
+use crate::{
+    directory::Directory,
+    keystore::{KdfParameters, Keystore, KeystoreError, Secret},
+    membership::Membership,
+    persistence::{self, FileKind, PersistenceError, LEGACY_VERSION},
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
 
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use serde::{Deserialize, Serialize};
 
-use std::{collections::HashMap, fs, iter};
+use std::{collections::HashMap, iter, path::Path};
+
+use rand::prelude::*;
+
+use rand_chacha::ChaCha20Rng;
 
 use talk::crypto::{Identity, KeyChain};
 
+/// A collection of `KeyChain`s, kept in generation order. The order is
+/// preserved by `save` and `save_encrypted`, and determines which `KeyChain`s
+/// `system` assigns to servers and to which client ids.
 #[derive(Serialize, Deserialize)]
+#[serde(from = "Serialized")]
 pub struct Passepartout {
-    keychains: HashMap<Identity, KeyChain>,
+    keychains: Vec<(Identity, KeyChain)>,
+    #[serde(skip)]
+    positions: HashMap<Identity, usize>, // Rebuilt from `keychains` on deserialization
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Passepartout")]
+struct Serialized {
+    keychains: Vec<(Identity, KeyChain)>,
 }
 
 const CHUNKS: usize = 64;
+const VERSION: u16 = 1;
 
 impl Passepartout {
+    fn from_keychains(keychains: Vec<(Identity, KeyChain)>) -> Self {
+        let positions = keychains
+            .iter()
+            .enumerate()
+            .map(|(position, (identity, _))| (*identity, position))
+            .collect::<HashMap<_, _>>();
+
+        Passepartout {
+            keychains,
+            positions,
+        }
+    }
+
     pub fn random(size: usize) -> Self {
         let keychains = (0..size)
             .into_par_iter()
@@ -24,31 +61,90 @@ impl Passepartout {
                 let identity = keychain.keycard().identity();
                 (identity, keychain)
             })
-            .collect::<HashMap<_, _>>();
+            .collect::<Vec<_>>();
 
-        Passepartout { keychains }
+        Passepartout::from_keychains(keychains)
     }
 
-    pub fn load(path: &str) -> Self {
-        let bytes = fs::read(path).unwrap();
+    /// Deterministically derives `size` `KeyChain`s from `seed`: the same
+    /// `seed` always yields the same `KeyChain`s, in the same order. The
+    /// `KeyChain` at position `index` does not depend on `size`.
+    pub fn from_seed(seed: u64, size: usize) -> Self {
+        let keychains = (0..size)
+            .into_par_iter()
+            .map(|index| {
+                let mut rng = ChaCha20Rng::seed_from_u64(seed);
+                rng.set_stream(index as u64);
 
-        let chunks = bincode::deserialize::<Vec<Vec<u8>>>(bytes.as_slice()).unwrap();
+                let keychain = KeyChain::from_seed(rng.gen());
+                let identity = keychain.keycard().identity();
+                (identity, keychain)
+            })
+            .collect::<Vec<_>>();
 
-        let keychains = chunks
+        Passepartout::from_keychains(keychains)
+    }
+
+    pub fn load<P>(path: P) -> Result<Self, Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = persistence::read(path)?;
+        let (version, body) = persistence::open(FileKind::Passepartout, bytes.as_slice())?;
+
+        // Version 1 only added the file header: the body is unchanged
+        if version != LEGACY_VERSION && version != VERSION {
+            return PersistenceError::UnsupportedVersion { version }
+                .fail()
+                .spot(here!());
+        }
+
+        let chunks = persistence::deserialize_body::<Vec<Vec<u8>>>(version, body)?;
+
+        let chunks = chunks
             .par_iter()
-            .map(|chunk| bincode::deserialize::<Vec<(Identity, KeyChain)>>(chunk).unwrap())
-            .flatten()
-            .collect::<HashMap<_, _>>();
+            .map(|chunk| persistence::deserialize::<Vec<(Identity, KeyChain)>>(chunk))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        Passepartout { keychains }
+        let keychains = chunks.into_iter().flatten().collect::<Vec<_>>();
+
+        Ok(Passepartout::from_keychains(keychains))
+    }
+
+    /// Loads a `Passepartout` from a keystore written by `save_encrypted`.
+    pub fn load_encrypted<P>(path: P, secret: &Secret) -> Result<Self, Top<KeystoreError>>
+    where
+        P: AsRef<Path>,
+    {
+        let keychains = Keystore::open(path, secret)?
+            .keychains()?
+            .into_iter()
+            .map(|keychain| (keychain.keycard().identity(), keychain))
+            .collect::<Vec<_>>();
+
+        Ok(Passepartout::from_keychains(keychains))
     }
 
     pub fn keychain(&self, identity: Identity) -> KeyChain {
-        self.keychains.get(&identity).unwrap().clone()
+        let position = *self.positions.get(&identity).unwrap();
+        self.keychains[position].1.clone()
+    }
+
+    pub fn contains(&self, identity: Identity) -> bool {
+        self.positions.contains_key(&identity)
     }
 
+    pub fn len(&self) -> usize {
+        self.keychains.len()
+    }
+
+    /// Assigns the first `servers` `KeyChain`s to servers, and the remaining
+    /// ones to clients, with consecutive ids starting from 0.
     pub fn system(&self, servers: usize) -> (Membership, Directory) {
-        let mut keycards = self.keychains.values().map(KeyChain::keycard);
+        let mut keycards = self
+            .keychains
+            .iter()
+            .map(|(_, keychain)| keychain.keycard());
 
         let servers = iter::repeat_with(|| keycards.next().unwrap()).take(servers);
         let membership = Membership::from_servers(servers);
@@ -59,21 +155,40 @@ impl Passepartout {
         (membership, directory)
     }
 
-    pub fn save(&self, path: &str) {
-        let keychains = self
+    pub fn save<P>(&self, path: P) -> Result<(), Top<PersistenceError>>
+    where
+        P: AsRef<Path>,
+    {
+        let chunk_size = (self.keychains.len() + CHUNKS - 1) / CHUNKS;
+
+        let chunks = self
             .keychains
-            .iter()
-            .map(|(id, keychain)| (*id, keychain.clone()))
-            .collect::<Vec<_>>();
+            .chunks(chunk_size.max(1))
+            .map(|chunk| persistence::serialize(&chunk))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let chunk_size = (keychains.len() + CHUNKS - 1) / CHUNKS;
+        let body = persistence::serialize(&chunks)?;
+        persistence::write(path, FileKind::Passepartout, VERSION, body.as_slice())
+    }
 
-        let chunks = keychains
-            .chunks(chunk_size)
-            .map(|chunk| bincode::serialize(&chunk).unwrap())
-            .collect::<Vec<_>>();
+    /// Saves every `KeyChain` to an encrypted keystore. Use `Keystore::open`
+    /// to extract individual `KeyChain`s without decrypting the others.
+    pub fn save_encrypted<P>(&self, path: P, secret: &Secret) -> Result<(), Top<KeystoreError>>
+    where
+        P: AsRef<Path>,
+    {
+        Keystore::save(
+            path,
+            self.keychains.iter().map(|(_, keychain)| keychain),
+            secret,
+            KdfParameters::random(),
+        )
+    }
+}
 
-        fs::write(path, bincode::serialize(&chunks).unwrap().as_slice()).unwrap();
+impl From<Serialized> for Passepartout {
+    fn from(serialized: Serialized) -> Self {
+        Passepartout::from_keychains(serialized.keychains)
     }
 }
 
@@ -97,7 +212,7 @@ mod tests {
     #[test]
     fn persist() {
         let original = Passepartout::random(1000);
-        original.save("assets/passepartout.bin");
+        original.save("assets/passepartout.bin").unwrap();
 
         let message = TestStatement(42);
 
@@ -107,7 +222,7 @@ mod tests {
             .map(|(identity, keychain)| (*identity, keychain.sign(&message).unwrap()))
             .collect::<HashMap<_, _>>();
 
-        let loaded = Passepartout::load("assets/passepartout.bin");
+        let loaded = Passepartout::load("assets/passepartout.bin").unwrap();
 
         for (identity, signature) in signatures {
             signature
@@ -115,4 +230,28 @@ mod tests {
                 .unwrap();
         }
     }
+
+    #[test]
+    fn from_seed() {
+        let first = Passepartout::from_seed(42, 20);
+        let second = Passepartout::from_seed(42, 30);
+
+        let (first_membership, first_directory) = first.system(4);
+        let (second_membership, second_directory) = second.system(4);
+
+        assert!(first_membership
+            .servers()
+            .keys()
+            .eq(second_membership.servers().keys()));
+
+        for id in 0..(first_directory.capacity() as u64) {
+            assert_eq!(
+                first_directory.keycard(id).unwrap().identity(),
+                second_directory.keycard(id).unwrap().identity()
+            );
+        }
+
+        let other = Passepartout::from_seed(43, 20);
+        assert_ne!(first.keychains[0].0, other.keychains[0].0);
+    }
 }
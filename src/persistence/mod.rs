@@ -0,0 +1,8 @@
+mod persistence;
+
+pub(crate) use persistence::{
+    deserialize, deserialize_body, open, open_unchecked, read, serialize, write, FileKind,
+    LEGACY_VERSION,
+};
+
+pub use persistence::PersistenceError;
@@ -0,0 +1,216 @@
+use bincode::Options;
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use sha1::{Digest, Sha1};
+
+use std::{convert::TryInto, fs, io, ops::Range, path::Path};
+
+// Every file written by pod starts with the following header (all integers
+// little-endian):
+//
+//   [0..4)   MAGIC
+//   [4..5)   kind
+//   [5..7)   format version
+//   [7..15)  body length (u64)
+//   [15..35) SHA-1 checksum of the body
+//
+// Files written before the header was introduced carry no header at all: they
+// are read as version `LEGACY_VERSION` and are rewritten in the current format
+// on the next `save`. Because a file whose magic is corrupted also looks like a
+// legacy file, legacy bodies are only accepted if they parse exactly (see
+// `deserialize_body`).
+const MAGIC: &[u8; 4] = b"pod\x00";
+pub(crate) const HEADER_SIZE: usize = 35;
+pub(crate) const LEGACY_VERSION: u16 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum FileKind {
+    Directory = 0,
+    IndexedDirectory = 1,
+    Membership = 2,
+    Passepartout = 3,
+    Keystore = 4,
+    ThresholdKeyChain = 5,
+}
+
+#[derive(Doom)]
+pub enum PersistenceError {
+    #[doom(description("Failed to read file: {:?}", source))]
+    #[doom(wrap(read_failed))]
+    ReadFailed { source: io::Error },
+    #[doom(description("Failed to write file: {:?}", source))]
+    #[doom(wrap(write_failed))]
+    WriteFailed { source: io::Error },
+    #[doom(description("Failed to serialize: {:?}", source))]
+    #[doom(wrap(serialize_failed))]
+    SerializeFailed { source: bincode::Error },
+    #[doom(description("Failed to deserialize: {:?}", source))]
+    #[doom(wrap(deserialize_failed))]
+    DeserializeFailed { source: bincode::Error },
+    #[doom(description("File has the wrong kind (expected {:?}, found {})", expected, found))]
+    WrongKind { expected: u8, found: u8 },
+    #[doom(description("Unsupported format version {}", version))]
+    UnsupportedVersion { version: u16 },
+    #[doom(description("File truncated"))]
+    Truncated,
+    #[doom(description("Checksum mismatch"))]
+    ChecksumMismatch,
+    #[doom(description("File header corrupted"))]
+    CorruptedHeader,
+}
+
+/// Returns the version and the (checksum-verified) body of `bytes`.
+pub(crate) fn open(kind: FileKind, bytes: &[u8]) -> Result<(u16, &[u8]), Top<PersistenceError>> {
+    let (version, body) = open_unchecked(kind, bytes)?;
+    let body = &bytes[body];
+
+    if version != LEGACY_VERSION && Sha1::digest(body).as_slice() != &bytes[15..HEADER_SIZE] {
+        return PersistenceError::ChecksumMismatch.fail().spot(here!());
+    }
+
+    Ok((version, body))
+}
+
+/// Like `open`, but skips the checksum and returns the range of the body
+/// within `bytes`. Used when `bytes` is too large to be hashed eagerly.
+pub(crate) fn open_unchecked(
+    kind: FileKind,
+    bytes: &[u8],
+) -> Result<(u16, Range<usize>), Top<PersistenceError>> {
+    if bytes.len() < MAGIC.len() || &bytes[0..MAGIC.len()] != MAGIC {
+        return Ok((LEGACY_VERSION, 0..bytes.len()));
+    }
+
+    if bytes.len() < HEADER_SIZE {
+        return PersistenceError::Truncated.fail().spot(here!());
+    }
+
+    if bytes[4] != kind as u8 {
+        return PersistenceError::WrongKind {
+            expected: kind as u8,
+            found: bytes[4],
+        }
+        .fail()
+        .spot(here!());
+    }
+
+    let version = u16::from_le_bytes(bytes[5..7].try_into().unwrap());
+    let length = u64::from_le_bytes(bytes[7..15].try_into().unwrap());
+
+    if (bytes.len() - HEADER_SIZE) as u64 != length {
+        return PersistenceError::Truncated.fail().spot(here!());
+    }
+
+    Ok((version, HEADER_SIZE..bytes.len()))
+}
+
+pub(crate) fn seal(kind: FileKind, version: u16, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(kind as u8);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bytes.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(Sha1::digest(body).as_slice());
+    bytes.extend_from_slice(body);
+
+    bytes
+}
+
+pub(crate) fn read<P>(path: P) -> Result<Vec<u8>, Top<PersistenceError>>
+where
+    P: AsRef<Path>,
+{
+    fs::read(path)
+        .map_err(PersistenceError::read_failed)
+        .map_err(PersistenceError::into_top)
+        .spot(here!())
+}
+
+pub(crate) fn write<P>(
+    path: P,
+    kind: FileKind,
+    version: u16,
+    body: &[u8],
+) -> Result<(), Top<PersistenceError>>
+where
+    P: AsRef<Path>,
+{
+    fs::write(path, seal(kind, version, body).as_slice())
+        .map_err(PersistenceError::write_failed)
+        .map_err(PersistenceError::into_top)
+        .spot(here!())
+}
+
+pub(crate) fn serialize<T>(value: &T) -> Result<Vec<u8>, Top<PersistenceError>>
+where
+    T: serde::Serialize + ?Sized,
+{
+    bincode::serialize(value)
+        .map_err(PersistenceError::serialize_failed)
+        .map_err(PersistenceError::into_top)
+        .spot(here!())
+}
+
+pub(crate) fn deserialize<'de, T>(bytes: &'de [u8]) -> Result<T, Top<PersistenceError>>
+where
+    T: serde::Deserialize<'de>,
+{
+    bincode::deserialize(bytes)
+        .map_err(PersistenceError::deserialize_failed)
+        .map_err(PersistenceError::into_top)
+        .spot(here!())
+}
+
+/// Deserializes the body of a file of version `version` (as returned by
+/// `open`). A legacy body must parse exactly, without trailing bytes:
+/// otherwise, the file is assumed to carry a corrupted header.
+pub(crate) fn deserialize_body<'de, T>(
+    version: u16,
+    body: &'de [u8],
+) -> Result<T, Top<PersistenceError>>
+where
+    T: serde::Deserialize<'de>,
+{
+    if version != LEGACY_VERSION {
+        return deserialize(body);
+    }
+
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(body)
+        .map_err(|_| PersistenceError::CorruptedHeader.into_top())
+        .spot(here!())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupted() {
+        let mut bytes = seal(FileKind::Membership, 1, b"some body");
+
+        let (version, body) = open(FileKind::Membership, bytes.as_slice()).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(body, b"some body");
+
+        assert!(open(FileKind::Directory, bytes.as_slice()).is_err());
+        assert!(open(FileKind::Membership, &bytes[..bytes.len() - 1]).is_err());
+
+        *bytes.last_mut().unwrap() ^= 1;
+        assert!(open(FileKind::Membership, bytes.as_slice()).is_err());
+
+        let mut bytes = seal(FileKind::Membership, 1, &serialize(&vec![0u64; 4]).unwrap());
+        bytes[0] ^= 1;
+
+        let (version, body) = open(FileKind::Membership, bytes.as_slice()).unwrap();
+        assert_eq!(version, LEGACY_VERSION);
+
+        let error = deserialize_body::<Vec<u64>>(version, body).err().unwrap();
+        assert!(matches!(error.top(), PersistenceError::CorruptedHeader));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    membership::Membership,
+    server::{OrderStatement, ReceiptStatement},
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::{
+    primitives::{hash::Hash, multi::Signature as MultiSignature},
+    Identity,
+};
+
+/// Transferable proof that a server misbehaved: two statements it signed
+/// for different batches at the same `Position`. Witness shards are not
+/// covered: a correct server signs them for any number of batches, so no two
+/// of them conflict.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Equivocation {
+    identity: Identity,
+    position: Position,
+    first: (Hash, MultiSignature),
+    second: (Hash, MultiSignature),
+}
+
+/// Where a correct server signs at most one root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Position {
+    /// A receipt (see `ReceiptStatement`): a correct server delivers exactly
+    /// one batch at every height.
+    Receipt { height: u64 },
+    /// An order shard (see `OrderStatement`): a correct server submits
+    /// exactly one batch in every slot.
+    Order { slot: u64 },
+}
+
+#[derive(Doom)]
+pub enum EquivocationError {
+    #[doom(description("Signer is not a server"))]
+    ForeignSigner,
+    #[doom(description("Statements do not conflict"))]
+    NoConflict,
+    #[doom(description("Receipt shard invalid"))]
+    ReceiptShardInvalid,
+    #[doom(description("Order shard invalid"))]
+    OrderShardInvalid,
+}
+
+impl Equivocation {
+    pub(in crate::server) fn new(
+        identity: Identity,
+        position: Position,
+        first: (Hash, MultiSignature),
+        second: (Hash, MultiSignature),
+    ) -> Self {
+        Equivocation {
+            identity,
+            position,
+            first,
+            second,
+        }
+    }
+
+    /// The misbehaving server.
+    pub fn identity(&self) -> Identity {
+        self.identity
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn verify(&self, membership: &Membership) -> Result<(), Top<EquivocationError>> {
+        let keycard = membership
+            .servers()
+            .get(&self.identity)
+            .ok_or_else(|| EquivocationError::ForeignSigner.into_top())
+            .spot(here!())?;
+
+        if self.first.0 == self.second.0 {
+            return EquivocationError::NoConflict.fail().spot(here!());
+        }
+
+        let domain = membership.domain();
+
+        for (root, shard) in [&self.first, &self.second] {
+            match self.position {
+                Position::Receipt { height } => shard
+                    .verify([keycard], &ReceiptStatement::new(domain, height, *root))
+                    .pot(EquivocationError::ReceiptShardInvalid, here!())?,
+                Position::Order { slot } => shard
+                    .verify([keycard], &OrderStatement::new(domain, slot, *root))
+                    .pot(EquivocationError::OrderShardInvalid, here!())?,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use crate::server::{server::RETENTION, Equivocation, Position};
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use talk::crypto::{
+    primitives::{hash::Hash, multi::Signature as MultiSignature},
+    Identity,
+};
+
+/// Retains the receipt and order shards signed by every server, and builds
+/// an `Equivocation` as soon as a server signs two conflicting ones. Shards
+/// more than `RETENTION` heights (or slots) older than the latest a server
+/// signed are forgotten.
+#[derive(Default)]
+pub struct EquivocationDetector {
+    shards: HashMap<(Identity, Position), (Hash, MultiSignature)>,
+    // Latest height, and latest slot, every server signed at
+    latest: HashMap<Identity, (u64, u64)>,
+    observed: u64,
+    equivocations: HashMap<Identity, Equivocation>,
+}
+
+impl EquivocationDetector {
+    pub fn new() -> Self {
+        EquivocationDetector::default()
+    }
+
+    /// Records the receipt shard `identity` signed for `root` at `height`
+    /// (which must have been verified). Returns a proof of misbehaviour if
+    /// `identity` previously signed a different root at `height`.
+    pub fn observe(
+        &mut self,
+        identity: Identity,
+        height: u64,
+        root: Hash,
+        shard: MultiSignature,
+    ) -> Option<Equivocation> {
+        self.record(identity, Position::Receipt { height }, root, shard)
+    }
+
+    /// Like `observe`, for the order shard `identity` signed for `root` in
+    /// `slot` (see `OrderStatement`).
+    pub fn observe_order(
+        &mut self,
+        identity: Identity,
+        slot: u64,
+        root: Hash,
+        shard: MultiSignature,
+    ) -> Option<Equivocation> {
+        self.record(identity, Position::Order { slot }, root, shard)
+    }
+
+    /// Whether `identity` was caught equivocating.
+    pub fn caught(&self, identity: Identity) -> bool {
+        self.equivocations.contains_key(&identity)
+    }
+
+    /// One proof for every server caught equivocating so far.
+    pub fn equivocations(&self) -> impl Iterator<Item = &Equivocation> {
+        self.equivocations.values()
+    }
+
+    fn record(
+        &mut self,
+        identity: Identity,
+        position: Position,
+        root: Hash,
+        shard: MultiSignature,
+    ) -> Option<Equivocation> {
+        // A server signing far ahead only makes its own shards expire
+        let latest = self.latest.entry(identity).or_default();
+
+        let (index, latest) = match position {
+            Position::Receipt { height } => (height, &mut latest.0),
+            Position::Order { slot } => (slot, &mut latest.1),
+        };
+
+        if index.saturating_add(RETENTION) < *latest {
+            return None;
+        }
+
+        *latest = (*latest).max(index);
+
+        self.observed += 1;
+
+        if self.observed % RETENTION == 0 {
+            self.collect_garbage();
+        }
+
+        match self.shards.entry((identity, position)) {
+            Entry::Vacant(entry) => {
+                entry.insert((root, shard));
+                None
+            }
+            Entry::Occupied(entry) => {
+                let first = *entry.get();
+
+                if first.0 == root {
+                    return None;
+                }
+
+                let equivocation = Equivocation::new(identity, position, first, (root, shard));
+
+                self.equivocations
+                    .entry(identity)
+                    .or_insert_with(|| equivocation.clone());
+
+                Some(equivocation)
+            }
+        }
+    }
+
+    // Forgets every shard more than `RETENTION` heights (or slots) older than
+    // the latest its server signed
+    fn collect_garbage(&mut self) {
+        let latest = &self.latest;
+
+        self.shards.retain(|(identity, position), _| {
+            let (height, slot) = latest.get(identity).copied().unwrap_or_default();
+
+            match position {
+                Position::Receipt { height: index } => index.saturating_add(RETENTION) >= height,
+                Position::Order { slot: index } => index.saturating_add(RETENTION) >= slot,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        passepartout::Passepartout,
+        server::{EquivocationError, OrderStatement, ReceiptStatement},
+    };
+
+    use talk::crypto::primitives::hash;
+
+    #[test]
+    fn observe() {
+        let passepartout = Passepartout::random(4);
+        let (membership, _) = passepartout.system(4);
+
+        let identity = *membership.servers().keys().next().unwrap();
+        let keychain = passepartout.keychain(identity);
+
+        let roots = [hash::hash(&0u64).unwrap(), hash::hash(&1u64).unwrap()];
+
+        let shards = roots.map(|root| {
+            keychain
+                .multisign(&ReceiptStatement::new(membership.domain(), 7, root))
+                .unwrap()
+        });
+
+        let mut detector = EquivocationDetector::new();
+
+        assert!(detector.observe(identity, 7, roots[0], shards[0]).is_none());
+        assert!(detector.observe(identity, 7, roots[0], shards[0]).is_none());
+
+        let equivocation = detector.observe(identity, 7, roots[1], shards[1]).unwrap();
+        equivocation.verify(&membership).unwrap();
+        assert_eq!(detector.equivocations().count(), 1);
+        assert!(detector.caught(identity));
+
+        let receipt = Position::Receipt { height: 7 };
+
+        let forged = Equivocation::new(
+            identity,
+            receipt,
+            (roots[0], shards[0]),
+            (roots[0], shards[0]),
+        );
+        let error = forged.verify(&membership).err().unwrap();
+        assert!(matches!(error.top(), EquivocationError::NoConflict));
+
+        let forged = Equivocation::new(
+            identity,
+            Position::Receipt { height: 8 },
+            (roots[0], shards[0]),
+            (roots[1], shards[1]),
+        );
+
+        let error = forged.verify(&membership).err().unwrap();
+        assert!(matches!(
+            error.top(),
+            EquivocationError::ReceiptShardInvalid
+        ));
+
+        // Receipt shards do not count as order shards
+        let forged = Equivocation::new(
+            identity,
+            Position::Order { slot: 7 },
+            (roots[0], shards[0]),
+            (roots[1], shards[1]),
+        );
+
+        let error = forged.verify(&membership).err().unwrap();
+        assert!(matches!(error.top(), EquivocationError::OrderShardInvalid));
+    }
+
+    #[test]
+    fn observe_order() {
+        let passepartout = Passepartout::random(4);
+        let (membership, _) = passepartout.system(4);
+
+        let identity = *membership.servers().keys().next().unwrap();
+        let keychain = passepartout.keychain(identity);
+
+        let order_shard = |slot, root| {
+            keychain
+                .multisign(&OrderStatement::new(membership.domain(), slot, root))
+                .unwrap()
+        };
+
+        let roots = [hash::hash(&0u64).unwrap(), hash::hash(&1u64).unwrap()];
+
+        let mut detector = EquivocationDetector::new();
+
+        // The same root in two slots is not a conflict
+        assert!(detector
+            .observe_order(identity, 3, roots[0], order_shard(3, roots[0]))
+            .is_none());
+        assert!(detector
+            .observe_order(identity, 4, roots[0], order_shard(4, roots[0]))
+            .is_none());
+
+        let equivocation = detector
+            .observe_order(identity, 3, roots[1], order_shard(3, roots[1]))
+            .unwrap();
+
+        assert_eq!(equivocation.position(), Position::Order { slot: 3 });
+        equivocation.verify(&membership).unwrap();
+        assert!(detector.caught(identity));
+
+        // Slots more than `RETENTION` behind the latest are forgotten
+        // The detector does not verify shards: a single one is reused
+        let mut detector = EquivocationDetector::new();
+        let shard = order_shard(0, roots[0]);
+
+        for slot in 0..(2 * RETENTION) {
+            detector.observe_order(identity, slot, roots[0], shard);
+        }
+
+        assert!(detector.shards.len() <= 2 * RETENTION as usize);
+        assert!(!detector
+            .shards
+            .contains_key(&(identity, Position::Order { slot: 0 })));
+
+        assert!(detector
+            .observe_order(identity, 0, roots[1], order_shard(0, roots[1]))
+            .is_none());
+        assert!(!detector.caught(identity));
+    }
+}
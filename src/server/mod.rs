@@ -1,8 +1,20 @@
+mod equivocation;
+mod equivocation_detector;
 mod order_statement;
+mod receipt;
+mod receipt_statement;
+mod request;
 mod server;
+mod submission;
 mod witness_statement;
 
-pub(crate) use order_statement::OrderStatement;
-pub(crate) use witness_statement::WitnessStatement;
+pub(crate) use request::Request;
+pub(crate) use submission::Submission;
 
+pub use equivocation::{Equivocation, EquivocationError, Position};
+pub use equivocation_detector::EquivocationDetector;
+pub use order_statement::OrderStatement;
+pub use receipt::{Receipt, ReceiptError};
+pub use receipt_statement::ReceiptStatement;
 pub use server::Server;
+pub use witness_statement::WitnessStatement;
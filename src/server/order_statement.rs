@@ -1,17 +1,23 @@
-use crate::crypto::Header;
+use crate::crypto::{Domain, Header};
 
 use serde::Serialize;
 
 use talk::crypto::{primitives::hash::Hash, Statement};
 
-#[derive(Serialize)]
-pub(crate) struct OrderStatement {
+/// Multi-signed by a server upon submitting the batch (or dispersed batch)
+/// with a given root (or commitment) to the ordering layer, as its `slot`-th
+/// submission (counting from 0). A correct server never signs two roots for
+/// the same slot.
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderStatement {
+    domain: Domain,
+    slot: u64,
     root: Hash,
 }
 
 impl OrderStatement {
-    pub fn new(root: Hash) -> Self {
-        OrderStatement { root }
+    pub fn new(domain: Domain, slot: u64, root: Hash) -> Self {
+        OrderStatement { domain, slot, root }
     }
 }
 
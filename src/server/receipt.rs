@@ -0,0 +1,122 @@
+use crate::{
+    batch::{InclusionProof, Payload},
+    membership::{Certificate, Membership},
+    server::ReceiptStatement,
+};
+
+use doomstack::{here, Doom, ResultExt, Top};
+
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::primitives::hash::Hash;
+
+/// Proves that the batch with root `root` was delivered as the `height`-th
+/// batch, to anyone holding the `Membership`: `certificate` aggregates the
+/// `ReceiptStatement`s of a quorum of servers. Together with an
+/// `InclusionProof`, proves the final delivery of a `Payload`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    height: u64,
+    root: Hash,
+    certificate: Certificate,
+}
+
+#[derive(Doom)]
+pub enum ReceiptError {
+    #[doom(description("Certificate invalid"))]
+    CertificateInvalid,
+    #[doom(description("Inclusion proof invalid"))]
+    InclusionProofInvalid,
+}
+
+impl Receipt {
+    pub(crate) fn new(height: u64, root: Hash, certificate: Certificate) -> Self {
+        Receipt {
+            height,
+            root,
+            certificate,
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn root(&self) -> Hash {
+        self.root
+    }
+
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+
+    pub fn verify(&self, membership: &Membership) -> Result<(), Top<ReceiptError>> {
+        let statement = ReceiptStatement::new(membership.domain(), self.height, self.root);
+
+        self.certificate
+            .verify_quorum(membership, &statement)
+            .pot(ReceiptError::CertificateInvalid, here!())
+    }
+
+    /// Checks both `self` and that `proof` includes `payload` in the batch.
+    pub fn verify_delivery(
+        &self,
+        membership: &Membership,
+        payload: &Payload,
+        proof: &InclusionProof,
+    ) -> Result<(), Top<ReceiptError>> {
+        self.verify(membership)?;
+
+        proof
+            .verify(self.root, payload)
+            .pot(ReceiptError::InclusionProofInvalid, here!())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{batch::Batch, passepartout::Passepartout};
+
+    #[test]
+    fn verify_delivery() {
+        let passepartout = Passepartout::random(100);
+        let (membership, directory) = passepartout.system(4);
+
+        let batch = Batch::random(&directory, &passepartout, 42);
+        let root = batch.root();
+
+        let statement = ReceiptStatement::new(membership.domain(), 3, root);
+
+        let shards = membership.servers().keys().take(3).map(|identity| {
+            let shard = passepartout
+                .keychain(*identity)
+                .multisign(&statement)
+                .unwrap();
+
+            (*identity, shard)
+        });
+
+        let receipt = Receipt::new(3, root, Certificate::aggregate_quorum(&membership, shards));
+
+        let payload = batch.payloads().next().unwrap();
+        let proof = batch.prove(payload.id, payload.sequence).unwrap();
+        receipt
+            .verify_delivery(&membership, payload, &proof)
+            .unwrap();
+
+        let mut forged = payload.clone();
+        forged.message[0] ^= 1;
+
+        let error = receipt
+            .verify_delivery(&membership, &forged, &proof)
+            .err()
+            .unwrap();
+        assert!(matches!(error.top(), ReceiptError::InclusionProofInvalid));
+
+        let forged = Receipt::new(4, root, receipt.certificate.clone());
+        let error = forged.verify(&membership).err().unwrap();
+        assert!(matches!(error.top(), ReceiptError::CertificateInvalid));
+    }
+}
@@ -0,0 +1,30 @@
+use crate::crypto::{Domain, Header};
+
+use serde::Serialize;
+
+use talk::crypto::{primitives::hash::Hash, Statement};
+
+/// Multi-signed by a server upon delivering the batch with a given root as
+/// the `height`-th batch (counting from 0). A quorum of receipts proves that
+/// the batch was finally delivered.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptStatement {
+    domain: Domain,
+    height: u64,
+    root: Hash,
+}
+
+impl ReceiptStatement {
+    pub fn new(domain: Domain, height: u64, root: Hash) -> Self {
+        ReceiptStatement {
+            domain,
+            height,
+            root,
+        }
+    }
+}
+
+impl Statement for ReceiptStatement {
+    type Header = Header;
+    const HEADER: Header = Header::Receipt;
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::primitives::hash::Hash;
+
+/// The first message of every session a `Server` accepts.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Request {
+    /// A broker submits a whole (compressed) batch, followed by its bytes.
+    /// The server witnesses the batch only if `verify` is set.
+    Batch { verify: bool },
+    /// A broker submits the fragment of a dispersed batch meant for the server.
+    Fragment,
+    /// A server asks for the fragment it stores for `commitment`.
+    Retrieve { commitment: Hash },
+    /// A broker asks for the server's receipt for the batch with `root`.
+    Receipt { root: Hash },
+    /// A broker submits an `Equivocation`, followed by its bytes, for the
+    /// server to order.
+    Report,
+}
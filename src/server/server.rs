@@ -1,25 +1,34 @@
 use crate::{
-    batch::{Batch, BatchError, CompressedBatch},
+    batch::{Batch, BatchError, CompressedBatchView},
     broadcast::Broadcast,
     directory::Directory,
-    membership::{Certificate, Membership},
-    server::{OrderStatement, WitnessStatement},
+    dispersal::{Dispersal, DispersalStatement, Fragment},
+    membership::{Certificate, CertificateVerifier, Membership},
+    server::{
+        Equivocation, OrderStatement, Position, ReceiptStatement, Request, Submission,
+        WitnessStatement,
+    },
 };
 
 use doomstack::{here, Doom, ResultExt, Top};
 
+use futures::stream::{FuturesOrdered, FuturesUnordered, StreamExt};
+
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
 use talk::{
     crypto::{
         primitives::{hash::Hash, multi::Signature as MultiSignature},
-        KeyChain,
+        Identity, KeyChain,
     },
-    net::{Session, SessionListener},
+    net::{Session, SessionConnector, SessionListener},
     sync::fuse::Fuse,
 };
 
@@ -33,29 +42,67 @@ use tokio::{
 
 const TASKS: usize = 48;
 const BATCH_POLL: Duration = Duration::from_millis(100);
+const FRAGMENT_POLL: Duration = Duration::from_millis(500);
+const BURST: usize = 64;
+
+// Fragments and receipts older than `RETENTION` batches, and roots and
+// commitments ordered more than `RETENTION` submissions ago, are forgotten
+pub(in crate::server) const RETENTION: u64 = 1024;
+
+// Batches received whole (see `Received`), by root
+type Batches = Arc<Mutex<HashMap<Hash, Received>>>;
+// Number of batches delivered so far
+type Height = Arc<AtomicU64>;
+// Number of submissions this server ordered so far (see `OrderStatement`)
+type Submitted = Arc<AtomicU64>;
+// Height at which every fragment was received, and the fragment, by commitment
+type Fragments = Arc<Mutex<HashMap<Hash, (u64, Fragment)>>>;
+// Height and receipt shard of every delivered batch, by root
+type Receipts = Arc<Mutex<HashMap<Hash, (u64, MultiSignature)>>>;
+// Servers proven to equivocate (see `Submission::Equivocation`)
+type Excluded = Arc<Mutex<HashSet<Identity>>>;
 
 pub struct Server {
     batch_receiver: UnboundedReceiver<Batch>,
+    excluded: Excluded,
     _fuse: Fuse,
 }
 
+// A batch this server verified is decompressed straight away. Otherwise,
+// only its root is computed (see `CompressedBatchView::root`), and the batch
+// is decompressed once delivered
+enum Received {
+    Verified(Batch),
+    Unverified(Vec<u8>),
+}
+
 #[derive(Doom)]
 enum ServeError {
     #[doom(description("Connection error"))]
     ConnectionError,
     #[doom(description("Batch invalid"))]
     BatchInvalid,
+    #[doom(description("Fragment invalid"))]
+    FragmentInvalid,
     #[doom(description("Witness invalid"))]
     WitnessInvalid,
+    #[doom(description("Equivocation invalid"))]
+    EquivocationInvalid,
 }
 
 #[derive(Doom)]
-enum ProcessError {
+pub(crate) enum ProcessError {
     #[doom(description("Failed to deserialize: {}", source))]
     #[doom(wrap(deserialize_failed))]
     DeserializeFailed { source: Box<bincode::ErrorKind> },
     #[doom(description("Witness invalid"))]
     WitnessInvalid,
+    #[doom(description("Failed to reconstruct dispersed batch"))]
+    ReconstructFailed,
+    #[doom(description("Batch invalid"))]
+    BatchInvalid,
+    #[doom(description("Equivocation invalid"))]
+    EquivocationInvalid,
 }
 
 impl Server {
@@ -65,38 +112,77 @@ impl Server {
         directory: Directory,
         broadcast: B,
         listener: SessionListener,
+        connector: SessionConnector,
     ) -> Self
     where
         B: Broadcast,
     {
+        let verifier = Arc::new(CertificateVerifier::new(membership));
         let broadcast = Arc::new(broadcast);
 
         let batches = HashMap::new();
         let batches = Arc::new(Mutex::new(batches));
 
+        let height = Arc::new(AtomicU64::new(0));
+        let submitted = Arc::new(AtomicU64::new(0));
+
+        let fragments = HashMap::new();
+        let fragments = Arc::new(Mutex::new(fragments));
+
+        let receipts = HashMap::new();
+        let receipts = Arc::new(Mutex::new(receipts));
+
+        let excluded = HashSet::new();
+        let excluded = Arc::new(Mutex::new(excluded));
+
         let (batch_sender, batch_receiver) = mpsc::unbounded_channel();
 
         let fuse = Fuse::new();
 
         {
-            let membership = membership.clone();
+            let keychain = keychain.clone();
+            let verifier = verifier.clone();
+            let directory = directory.clone();
             let broadcast = broadcast.clone();
             let batches = batches.clone();
+            let height = height.clone();
+            let fragments = fragments.clone();
+            let receipts = receipts.clone();
+            let excluded = excluded.clone();
 
             fuse.spawn(async move {
                 Server::listen(
-                    keychain, membership, directory, broadcast, batches, listener,
+                    keychain, verifier, directory, broadcast, batches, height, submitted,
+                    fragments, receipts, excluded, listener,
                 )
                 .await;
             });
         }
 
-        fuse.spawn(async move {
-            Server::deliver(membership, broadcast, batches, batch_sender).await;
-        });
+        {
+            let excluded = excluded.clone();
+
+            fuse.spawn(async move {
+                Server::deliver(
+                    keychain,
+                    verifier,
+                    directory,
+                    broadcast,
+                    batches,
+                    height,
+                    fragments,
+                    receipts,
+                    excluded,
+                    connector,
+                    batch_sender,
+                )
+                .await;
+            });
+        }
 
         Server {
             batch_receiver,
+            excluded,
             _fuse: fuse,
         }
     }
@@ -105,15 +191,26 @@ impl Server {
         self.batch_receiver.recv().await.unwrap()
     }
 
+    /// Servers proven to equivocate so far. Correct servers no longer count
+    /// their signatures towards certificates, nor ask them for fragments.
+    pub fn excluded(&self) -> HashSet<Identity> {
+        self.excluded.lock().unwrap().clone()
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn listen(
         keychain: KeyChain,
-        membership: Membership,
+        verifier: Arc<CertificateVerifier>,
         directory: Directory,
         broadcast: Arc<dyn Broadcast>,
-        batches: Arc<Mutex<HashMap<Hash, Batch>>>,
+        batches: Batches,
+        height: Height,
+        submitted: Submitted,
+        fragments: Fragments,
+        receipts: Receipts,
+        excluded: Excluded,
         mut listener: SessionListener,
     ) {
-        let membership = Arc::new(membership);
         let directory = Arc::new(directory);
 
         let semaphore = Semaphore::new(TASKS);
@@ -125,15 +222,21 @@ impl Server {
             let (_, session) = listener.accept().await;
 
             let keychain = keychain.clone();
-            let membership = membership.clone();
+            let verifier = verifier.clone();
             let directory = directory.clone();
             let broadcast = broadcast.clone();
             let batches = batches.clone();
+            let height = height.clone();
+            let submitted = submitted.clone();
+            let fragments = fragments.clone();
+            let receipts = receipts.clone();
+            let excluded = excluded.clone();
             let semaphore = semaphore.clone();
 
             fuse.spawn(async move {
                 if let Err(error) = Server::serve(
-                    keychain, membership, directory, broadcast, batches, semaphore, session,
+                    keychain, verifier, directory, broadcast, batches, height, submitted,
+                    fragments, receipts, excluded, semaphore, session,
                 )
                 .await
                 {
@@ -143,22 +246,65 @@ impl Server {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn serve(
         keychain: KeyChain,
-        membership: Arc<Membership>,
+        verifier: Arc<CertificateVerifier>,
         directory: Arc<Directory>,
         broadcast: Arc<dyn Broadcast>,
-        batches: Arc<Mutex<HashMap<Hash, Batch>>>,
+        batches: Batches,
+        height: Height,
+        submitted: Submitted,
+        fragments: Fragments,
+        receipts: Receipts,
+        excluded: Excluded,
         semaphore: Arc<Semaphore>,
         mut session: Session,
     ) -> Result<(), Top<ServeError>> {
-        let batch = session
-            .receive_raw_bytes()
+        let request = session
+            .receive_raw::<Request>()
             .await
             .pot(ServeError::ConnectionError, here!())?;
 
-        let verify = session
-            .receive_raw::<bool>()
+        match request {
+            Request::Batch { verify } => {
+                Server::serve_batch(
+                    keychain, verifier, directory, broadcast, batches, submitted, excluded,
+                    semaphore, session, verify,
+                )
+                .await
+            }
+            Request::Fragment => {
+                Server::serve_fragment(
+                    keychain, verifier, broadcast, height, submitted, fragments, excluded, session,
+                )
+                .await
+            }
+            Request::Retrieve { commitment } => {
+                Server::serve_retrieve(fragments, commitment, session).await
+            }
+            Request::Receipt { root } => Server::serve_receipt(receipts, root, session).await,
+            Request::Report => Server::serve_report(verifier, broadcast, excluded, session).await,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn serve_batch(
+        keychain: KeyChain,
+        verifier: Arc<CertificateVerifier>,
+        directory: Arc<Directory>,
+        broadcast: Arc<dyn Broadcast>,
+        batches: Batches,
+        submitted: Submitted,
+        excluded: Excluded,
+        semaphore: Arc<Semaphore>,
+        mut session: Session,
+        verify: bool,
+    ) -> Result<(), Top<ServeError>> {
+        let domain = verifier.membership().domain();
+
+        let batch = session
+            .receive_raw_bytes()
             .await
             .pot(ServeError::ConnectionError, here!())?;
 
@@ -168,28 +314,25 @@ impl Server {
 
             task::spawn_blocking(
                 move || -> Result<(Hash, Option<MultiSignature>), Top<BatchError>> {
-                    let batch = bincode::deserialize::<CompressedBatch>(batch.as_slice())
-                        .map_err(BatchError::deserialize_failed)
-                        .map_err(BatchError::into_top)
-                        .spot(here!())?;
+                    let (root, received, witness_shard) = if verify {
+                        let batch = CompressedBatchView::new(batch.as_slice())?.into_batch();
+                        let root = batch.root();
 
-                    let batch = batch.decompress();
-                    let root = batch.root();
+                        batch.verify(domain, directory.as_ref())?;
 
-                    let witness_shard = if verify {
-                        batch.verify(directory.as_ref())?;
+                        let witness_shard = keychain
+                            .multisign(&WitnessStatement::new(domain, root))
+                            .unwrap();
 
-                        let witness_shard =
-                            keychain.multisign(&WitnessStatement::new(root)).unwrap();
-
-                        Some(witness_shard)
+                        (root, Received::Verified(batch), Some(witness_shard))
                     } else {
-                        None
+                        let root = CompressedBatchView::new(batch.as_slice())?.root();
+                        (root, Received::Unverified(batch), None)
                     };
 
                     {
                         let mut batches = batches.lock().unwrap();
-                        batches.insert(root, batch);
+                        batches.insert(root, received);
                     }
 
                     Ok((root, witness_shard))
@@ -212,72 +355,671 @@ impl Server {
             .await
             .pot(ServeError::ConnectionError, here!())?;
 
-        witness
-            .verify_plurality(membership.as_ref(), &WitnessStatement::new(root))
+        // Signatures of excluded servers do not count towards the witness
+        let threshold = verifier.membership().plurality()
+            + witness.excluded_power(verifier.membership(), &excluded.lock().unwrap());
+
+        verifier
+            .verify_threshold(&witness, &WitnessStatement::new(domain, root), threshold)
+            .pot(ServeError::WitnessInvalid, here!())?;
+
+        // Every submission is ordered in a slot of its own
+        let slot = submitted.fetch_add(1, Ordering::Relaxed);
+
+        let order_shard = keychain
+            .multisign(&OrderStatement::new(domain, slot, root))
+            .unwrap();
+
+        session
+            .send_raw(&(slot, order_shard))
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        session.end();
+
+        let submission = bincode::serialize(&Submission::Batch { root, witness }).unwrap();
+        broadcast.order(submission.as_slice()).await;
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn serve_fragment(
+        keychain: KeyChain,
+        verifier: Arc<CertificateVerifier>,
+        broadcast: Arc<dyn Broadcast>,
+        height: Height,
+        submitted: Submitted,
+        fragments: Fragments,
+        excluded: Excluded,
+        mut session: Session,
+    ) -> Result<(), Top<ServeError>> {
+        let domain = verifier.membership().domain();
+
+        let (commitment, fragment) = session
+            .receive_raw::<(Hash, Fragment)>()
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        fragment
+            .verify(commitment, verifier.membership().servers().len())
+            .pot(ServeError::FragmentInvalid, here!())?;
+
+        // The `i`-th fragment is meant for the `i`-th server
+        let identity = keychain.keycard().identity();
+
+        if verifier.membership().servers().keys().nth(fragment.index()) != Some(&identity) {
+            return ServeError::FragmentInvalid.fail().spot(here!());
+        }
+
+        {
+            let height = height.load(Ordering::Relaxed);
+            let mut fragments = fragments.lock().unwrap();
+            fragments.insert(commitment, (height, fragment));
+        }
+
+        let witness_shard = keychain
+            .multisign(&DispersalStatement::new(domain, commitment))
+            .unwrap();
+
+        session
+            .send_raw(&witness_shard)
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        let witness = session
+            .receive_raw::<Certificate>()
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        let threshold = verifier.membership().quorum()
+            + witness.excluded_power(verifier.membership(), &excluded.lock().unwrap());
+
+        verifier
+            .verify_threshold(
+                &witness,
+                &DispersalStatement::new(domain, commitment),
+                threshold,
+            )
             .pot(ServeError::WitnessInvalid, here!())?;
 
-        let order_shard = keychain.multisign(&OrderStatement::new(root)).unwrap();
+        let slot = submitted.fetch_add(1, Ordering::Relaxed);
+
+        let order_shard = keychain
+            .multisign(&OrderStatement::new(domain, slot, commitment))
+            .unwrap();
 
         session
-            .send_raw(&order_shard)
+            .send_raw(&(slot, order_shard))
             .await
             .pot(ServeError::ConnectionError, here!())?;
 
         session.end();
 
-        let submission = bincode::serialize(&(root, witness)).unwrap();
+        let submission = bincode::serialize(&Submission::Dispersed {
+            commitment,
+            witness,
+        })
+        .unwrap();
+
         broadcast.order(submission.as_slice()).await;
 
         Ok(())
     }
 
+    async fn serve_retrieve(
+        fragments: Fragments,
+        commitment: Hash,
+        mut session: Session,
+    ) -> Result<(), Top<ServeError>> {
+        let fragment = fragments
+            .lock()
+            .unwrap()
+            .get(&commitment)
+            .map(|(_, fragment)| fragment.clone());
+
+        session
+            .send_raw(&fragment)
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        session.end();
+        Ok(())
+    }
+
+    async fn serve_receipt(
+        receipts: Receipts,
+        root: Hash,
+        mut session: Session,
+    ) -> Result<(), Top<ServeError>> {
+        let receipt = receipts.lock().unwrap().get(&root).copied();
+
+        session
+            .send_raw(&receipt)
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        session.end();
+        Ok(())
+    }
+
+    async fn serve_report(
+        verifier: Arc<CertificateVerifier>,
+        broadcast: Arc<dyn Broadcast>,
+        excluded: Excluded,
+        mut session: Session,
+    ) -> Result<(), Top<ServeError>> {
+        let equivocation = session
+            .receive_raw::<Equivocation>()
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        equivocation
+            .verify(verifier.membership())
+            .pot(ServeError::EquivocationInvalid, here!())?;
+
+        // The broker stops reporting once a server acknowledges
+        session
+            .send_raw(&())
+            .await
+            .pot(ServeError::ConnectionError, here!())?;
+
+        session.end();
+
+        if excluded.lock().unwrap().contains(&equivocation.identity()) {
+            return Ok(());
+        }
+
+        let submission = bincode::serialize(&Submission::Equivocation { equivocation }).unwrap();
+        broadcast.order(submission.as_slice()).await;
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn deliver(
-        membership: Membership,
+        keychain: KeyChain,
+        verifier: Arc<CertificateVerifier>,
+        directory: Directory,
         broadcast: Arc<dyn Broadcast>,
-        batches: Arc<Mutex<HashMap<Hash, Batch>>>,
+        batches: Batches,
+        height: Height,
+        fragments: Fragments,
+        receipts: Receipts,
+        excluded: Excluded,
+        connector: SessionConnector,
         batch_sender: UnboundedSender<Batch>,
     ) {
+        let identity = keychain.keycard().identity();
+        let domain = verifier.membership().domain();
+
+        // `Broadcast::deliver` is not cancel-safe: a dedicated task feeds
+        // submissions to a channel, which can be drained without blocking
+        let (submission_sender, mut submission_receiver) = mpsc::unbounded_channel();
+
+        let fuse = Fuse::new();
+
+        fuse.spawn(async move {
+            loop {
+                let submission = broadcast.deliver().await;
+
+                if submission_sender.send(submission).is_err() {
+                    return;
+                }
+            }
+        });
+
+        // Submissions are processed concurrently (in particular, dispersed
+        // batches are reconstructed while later submissions are delivered),
+        // but their batches are delivered in order
+        let mut pending = FuturesOrdered::new();
+
+        // Every server that checks a witness orders its submission: each root
+        // (or commitment) is only processed the first time it is ordered.
+        // Entries are indexed by position in the order (rather than by
+        // height, which advances asynchronously) so that every correct server
+        // forgets the same ones
+        let mut ordered = 0;
+        let mut processed = HashMap::new();
+
         loop {
-            let submission = broadcast.deliver().await;
-            let _ = Server::process(
-                &membership,
-                batches.as_ref(),
-                submission.as_slice(),
-                &batch_sender,
-            )
-            .await;
+            tokio::select! {
+                submission = submission_receiver.recv() => {
+                    let mut submissions = vec![submission.unwrap()];
+
+                    // Witnesses of submissions delivered in a burst are verified together
+                    while submissions.len() < BURST {
+                        match submission_receiver.try_recv() {
+                            Ok(submission) => submissions.push(submission),
+                            Err(_) => break,
+                        }
+                    }
+
+                    let submissions = Server::parse_submissions(
+                        verifier.as_ref(),
+                        &excluded.lock().unwrap().clone(),
+                        submissions.iter().map(Vec::as_slice),
+                    );
+
+                    for submission in submissions.into_iter().flatten() {
+                        let digest = match &submission {
+                            Submission::Batch { root, .. } => *root,
+                            Submission::Dispersed { commitment, .. } => *commitment,
+                            // Every correct server delivers the proof at the
+                            // same point, and excludes the culprit from then on
+                            // (later proofs against the same server change
+                            // nothing)
+                            Submission::Equivocation { equivocation } => {
+                                excluded.lock().unwrap().insert(equivocation.identity());
+                                continue;
+                            }
+                        };
+
+                        ordered += 1;
+
+                        if ordered % RETENTION == 0 {
+                            let horizon = ordered - RETENTION;
+                            processed.retain(|_, position| *position >= horizon);
+                        }
+
+                        if processed.insert(digest, ordered).is_some() {
+                            continue;
+                        }
+
+                        pending.push_back(Server::process(
+                            identity,
+                            verifier.membership(),
+                            &directory,
+                            batches.as_ref(),
+                            fragments.as_ref(),
+                            excluded.as_ref(),
+                            &connector,
+                            submission,
+                        ));
+                    }
+                }
+                Some(outcome) = pending.next(), if !pending.is_empty() => {
+                    let batch = match outcome {
+                        Ok(Some(batch)) => batch,
+                        _ => continue,
+                    };
+
+                    // Whether a submission yields a batch only depends on the
+                    // submission itself (see `process`): every correct server
+                    // delivers (and skips) the same batches in the same order
+                    let current = height.load(Ordering::Relaxed);
+                    let root = batch.root();
+
+                    let receipt = keychain
+                        .multisign(&ReceiptStatement::new(domain, current, root))
+                        .unwrap();
+
+                    receipts
+                        .lock()
+                        .unwrap()
+                        .entry(root)
+                        .or_insert((current, receipt));
+
+                    height.store(current + 1, Ordering::Relaxed);
+
+                    if (current + 1) % RETENTION == 0 {
+                        Server::collect_garbage(current + 1, fragments.as_ref(), receipts.as_ref());
+                    }
+
+                    let _ = batch_sender.send(batch);
+                }
+            }
         }
     }
 
+    // Forgets fragments received, and receipts issued, more than `RETENTION`
+    // batches before `height`
+    fn collect_garbage(
+        height: u64,
+        fragments: &Mutex<HashMap<Hash, (u64, Fragment)>>,
+        receipts: &Mutex<HashMap<Hash, (u64, MultiSignature)>>,
+    ) {
+        let horizon = height.saturating_sub(RETENTION);
+
+        fragments
+            .lock()
+            .unwrap()
+            .retain(|_, (received, _)| *received >= horizon);
+
+        receipts
+            .lock()
+            .unwrap()
+            .retain(|_, (delivered, _)| *delivered >= horizon);
+    }
+
+    // Returns `None` if `submission` carries no batch. Waits for as long as
+    // it takes to obtain the batch: errors only stem from the batch itself
+    #[allow(clippy::too_many_arguments)]
     async fn process(
+        identity: Identity,
         membership: &Membership,
-        batches: &Mutex<HashMap<Hash, Batch>>,
-        submission: &[u8],
-        batch_sender: &UnboundedSender<Batch>,
-    ) -> Result<(), Top<ProcessError>> {
-        let (root, witness) = bincode::deserialize::<(Hash, Certificate)>(submission)
-            .map_err(ProcessError::deserialize_failed)
-            .map_err(ProcessError::into_top)
-            .spot(here!())?;
-
-        witness
-            .verify_plurality(&membership, &WitnessStatement::new(root))
-            .pot(ProcessError::WitnessInvalid, here!())?;
-
-        let batch = loop {
-            {
-                let mut batches = batches.lock().unwrap();
-
-                if let Some(batch) = batches.remove(&root) {
-                    break batch;
+        directory: &Directory,
+        batches: &Mutex<HashMap<Hash, Received>>,
+        fragments: &Mutex<HashMap<Hash, (u64, Fragment)>>,
+        excluded: &Mutex<HashSet<Identity>>,
+        connector: &SessionConnector,
+        submission: Submission,
+    ) -> Result<Option<Batch>, Top<ProcessError>> {
+        let batch = match submission {
+            Submission::Batch { root, .. } => loop {
+                let received = batches.lock().unwrap().remove(&root);
+
+                match received {
+                    Some(Received::Verified(batch)) => break batch,
+                    // Already parsed once, when received
+                    Some(Received::Unverified(bytes)) => {
+                        break CompressedBatchView::new(bytes.as_slice())
+                            .pot(ProcessError::BatchInvalid, here!())?
+                            .into_batch()
+                    }
+                    None => {}
                 }
+
+                time::sleep(BATCH_POLL).await;
+            },
+            Submission::Dispersed { commitment, .. } => {
+                let bytes = Server::reconstruct(
+                    identity, membership, fragments, excluded, connector, commitment,
+                )
+                .await?;
+
+                // No server could verify the batch before it was ordered: if
+                // it turns out invalid, every correct server skips it
+                let batch = CompressedBatchView::new(bytes.as_slice())
+                    .pot(ProcessError::BatchInvalid, here!())?
+                    .into_batch();
+
+                batch
+                    .verify(membership.domain(), directory)
+                    .pot(ProcessError::BatchInvalid, here!())?;
+
+                batch
             }
+            // Applied as soon as delivered (see `deliver`)
+            Submission::Equivocation { .. } => return Ok(None),
+        };
+
+        Ok(Some(batch))
+    }
+
+    /// Collects enough fragments for `commitment` (starting from the one
+    /// stored locally, then asking all other servers) and reconstructs the
+    /// dispersed batch. Retries until enough fragments are collected: a quorum
+    /// of servers stored their fragment before the batch was ordered, and
+    /// giving up would make delivery depend on local timing. Once enough
+    /// fragments are collected, every server obtains the same outcome (see
+    /// `Dispersal::decode`).
+    async fn reconstruct(
+        identity: Identity,
+        membership: &Membership,
+        fragments: &Mutex<HashMap<Hash, (u64, Fragment)>>,
+        excluded: &Mutex<HashSet<Identity>>,
+        connector: &SessionConnector,
+        commitment: Hash,
+    ) -> Result<Vec<u8>, Top<ProcessError>> {
+        let dispersal = Dispersal::new(membership);
+        let mut collected = HashMap::new();
+
+        if let Some((_, fragment)) = fragments.lock().unwrap().get(&commitment).cloned() {
+            collected.insert(fragment.index(), fragment);
+        }
 
-            time::sleep(BATCH_POLL).await;
+        while collected.len() < dispersal.data() {
+            let excluded = excluded.lock().unwrap().clone();
+
+            let mut requests = membership
+                .servers()
+                .keys()
+                .enumerate()
+                .filter(|(index, server)| {
+                    **server != identity
+                        && !excluded.contains(*server)
+                        && !collected.contains_key(index)
+                })
+                .map(|(_, server)| Server::retrieve(connector, *server, commitment))
+                .collect::<FuturesUnordered<_>>();
+
+            while let Some(fragment) = requests.next().await {
+                if let Some(fragment) = fragment {
+                    if fragment.verify(commitment, dispersal.total()).is_ok() {
+                        collected.insert(fragment.index(), fragment);
+                    }
+                }
+
+                if collected.len() >= dispersal.data() {
+                    break;
+                }
+            }
+
+            if collected.len() < dispersal.data() {
+                // Some servers might not have received their fragment yet
+                time::sleep(FRAGMENT_POLL).await;
+            }
+        }
+
+        dispersal
+            .decode(commitment, collected.into_values())
+            .pot(ProcessError::ReconstructFailed, here!())
+    }
+
+    async fn retrieve(
+        connector: &SessionConnector,
+        server: Identity,
+        commitment: Hash,
+    ) -> Option<Fragment> {
+        let mut session = connector.connect(server).await.ok()?;
+
+        session
+            .send_raw(&Request::Retrieve { commitment })
+            .await
+            .ok()?;
+
+        let fragment = session.receive_raw::<Option<Fragment>>().await.ok()?;
+        session.end();
+
+        fragment
+    }
+
+    /// Deserializes submissions delivered by the ordering layer and checks
+    /// their witnesses, in batch. Signatures of servers in `excluded`, or
+    /// proven to equivocate by an earlier submission, do not count towards
+    /// witnesses. Returns one outcome per submission, in order.
+    pub(crate) fn parse_submissions<'s, I>(
+        verifier: &CertificateVerifier,
+        excluded: &HashSet<Identity>,
+        submissions: I,
+    ) -> Vec<Result<Submission, Top<ProcessError>>>
+    where
+        I: IntoIterator<Item = &'s [u8]>,
+    {
+        let mut submissions = submissions
+            .into_iter()
+            .map(|submission| {
+                bincode::deserialize::<Submission>(submission)
+                    .map_err(ProcessError::deserialize_failed)
+                    .map_err(ProcessError::into_top)
+                    .spot(here!())
+            })
+            .collect::<Vec<_>>();
+
+        let membership = verifier.membership();
+        let domain = membership.domain();
+
+        let mut excluded = excluded.clone();
+
+        let mut witnesses = Vec::new();
+        let mut dispersals = Vec::new();
+        let mut equivocations = Vec::new();
+
+        for (index, submission) in submissions.iter().enumerate() {
+            match submission {
+                Ok(Submission::Batch { root, witness }) => witnesses.push((
+                    index,
+                    witness,
+                    WitnessStatement::new(domain, *root),
+                    membership.plurality() + witness.excluded_power(membership, &excluded),
+                )),
+                Ok(Submission::Dispersed {
+                    commitment,
+                    witness,
+                }) => dispersals.push((
+                    index,
+                    witness,
+                    DispersalStatement::new(domain, *commitment),
+                    membership.quorum() + witness.excluded_power(membership, &excluded),
+                )),
+                Ok(Submission::Equivocation { equivocation }) => {
+                    let outcome = equivocation.verify(membership);
+
+                    if outcome.is_ok() {
+                        excluded.insert(equivocation.identity());
+                    }
+
+                    equivocations.push((index, outcome))
+                }
+                Err(_) => {}
+            }
+        }
+
+        let witness_outcomes = verifier.verify_batch(
+            witnesses
+                .iter()
+                .map(|(_, witness, statement, threshold)| (*witness, statement, *threshold)),
+        );
+
+        let dispersal_outcomes = verifier.verify_batch(
+            dispersals
+                .iter()
+                .map(|(_, witness, statement, threshold)| (*witness, statement, *threshold)),
+        );
+
+        let failures = witnesses
+            .iter()
+            .map(|(index, _, _, _)| *index)
+            .zip(witness_outcomes)
+            .chain(
+                dispersals
+                    .iter()
+                    .map(|(index, _, _, _)| *index)
+                    .zip(dispersal_outcomes),
+            )
+            .filter_map(|(index, outcome)| outcome.err().map(|error| (index, error)))
+            .collect::<Vec<_>>();
+
+        for (index, error) in failures {
+            submissions[index] = Err(error).pot(ProcessError::WitnessInvalid, here!());
+        }
+
+        let failures = equivocations
+            .into_iter()
+            .filter_map(|(index, outcome)| outcome.err().map(|error| (index, error)))
+            .collect::<Vec<_>>();
+
+        for (index, error) in failures {
+            submissions[index] = Err(error).pot(ProcessError::EquivocationInvalid, here!());
+        }
+
+        submissions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::passepartout::Passepartout;
+
+    use talk::crypto::primitives::hash;
+
+    #[test]
+    fn exclusion() {
+        let passepartout = Passepartout::random(4);
+        let (membership, _) = passepartout.system(4);
+        let verifier = CertificateVerifier::new(membership.clone());
+
+        let domain = membership.domain();
+        let servers = membership.servers().keys().copied().collect::<Vec<_>>();
+        let culprit = servers[0];
+
+        let receipts = [0u64, 1].map(|index| {
+            let root = hash::hash(&index).unwrap();
+
+            let shard = passepartout
+                .keychain(culprit)
+                .multisign(&ReceiptStatement::new(domain, 0, root))
+                .unwrap();
+
+            (root, shard)
+        });
+
+        let equivocation = Equivocation::new(
+            culprit,
+            Position::Receipt { height: 0 },
+            receipts[0],
+            receipts[1],
+        );
+
+        let root = hash::hash(&2u64).unwrap();
+
+        let witness = |signers: &[Identity]| {
+            Certificate::aggregate(
+                &membership,
+                signers.iter().map(|identity| {
+                    let shard = passepartout
+                        .keychain(*identity)
+                        .multisign(&WitnessStatement::new(domain, root))
+                        .unwrap();
+
+                    (*identity, shard)
+                }),
+            )
         };
 
-        let _ = batch_sender.send(batch);
+        // A plurality is 2: `servers[..2]` needs the culprit's weight
+        let submissions = [
+            Submission::Batch {
+                root,
+                witness: witness(&servers[..2]),
+            },
+            Submission::Equivocation { equivocation },
+            Submission::Batch {
+                root,
+                witness: witness(&servers[..2]),
+            },
+            Submission::Batch {
+                root,
+                witness: witness(&servers[1..3]),
+            },
+        ]
+        .map(|submission| bincode::serialize(&submission).unwrap());
 
-        Ok(())
+        let outcomes = Server::parse_submissions(
+            &verifier,
+            &HashSet::new(),
+            submissions.iter().map(Vec::as_slice),
+        );
+
+        assert!(outcomes[0].is_ok());
+        assert!(outcomes[1].is_ok());
+        assert!(matches!(
+            outcomes[2].as_ref().err().unwrap().top(),
+            ProcessError::WitnessInvalid
+        ));
+        assert!(outcomes[3].is_ok());
+
+        // The proof was delivered in an earlier burst
+        let excluded = HashSet::from([culprit]);
+
+        let outcomes = Server::parse_submissions(&verifier, &excluded, [submissions[0].as_slice()]);
+
+        assert!(matches!(
+            outcomes[0].as_ref().err().unwrap().top(),
+            ProcessError::WitnessInvalid
+        ));
     }
 }
@@ -0,0 +1,22 @@
+use crate::{membership::Certificate, server::Equivocation};
+
+use serde::{Deserialize, Serialize};
+
+use talk::crypto::primitives::hash::Hash;
+
+/// What a `Server` submits to the ordering layer.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Submission {
+    /// A batch every server received whole, witnessed by a plurality
+    /// (see `WitnessStatement`).
+    Batch { root: Hash, witness: Certificate },
+    /// A batch dispersed across servers, witnessed by a quorum (see
+    /// `DispersalStatement`). Servers reconstruct it once ordered.
+    Dispersed {
+        commitment: Hash,
+        witness: Certificate,
+    },
+    /// Proof that a server misbehaved. Once delivered, every correct server
+    /// excludes the culprit.
+    Equivocation { equivocation: Equivocation },
+}
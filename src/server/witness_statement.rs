@@ -1,17 +1,20 @@
-use crate::crypto::Header;
+use crate::crypto::{Domain, Header};
 
 use serde::Serialize;
 
 use talk::crypto::{primitives::hash::Hash, Statement};
 
-#[derive(Serialize)]
-pub(crate) struct WitnessStatement {
+/// Multi-signed by a server that stored (and, if requested, verified) the
+/// batch with a given root. A plurality of witnesses makes a batch orderable.
+#[derive(Debug, Clone, Serialize)]
+pub struct WitnessStatement {
+    domain: Domain,
     root: Hash,
 }
 
 impl WitnessStatement {
-    pub fn new(root: Hash) -> Self {
-        WitnessStatement { root }
+    pub fn new(domain: Domain, root: Hash) -> Self {
+        WitnessStatement { domain, root }
     }
 }
 